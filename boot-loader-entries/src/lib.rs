@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 mod parser;
 
@@ -26,6 +26,9 @@ impl From<nom::Err<nom::error::Error<&str>>> for Error {
 
 /// Trait to query associated file options on keys
 pub trait BootFile {
-    /// Request an associated file on a keyword/directive
-    fn boot_file(&self) -> Option<&Path>;
+    /// Request the associated files on a keyword/directive
+    fn boot_files(&self) -> Vec<&Path>;
+
+    /// Request the associated files on a keyword/directive, so that they can be changed
+    fn boot_files_mut(&mut self) -> Vec<&mut PathBuf>;
 }
@@ -1,10 +1,11 @@
 use nom::{
     bytes::complete::{tag_no_case, take_till1},
-    character::complete::{line_ending, space1},
+    character::complete::{line_ending, space0, space1},
     combinator::opt,
+    error::{Error, ErrorKind},
     multi::{many1, separated_list0},
-    sequence::{separated_pair, terminated},
-    IResult, InputTakeAtPosition, Parser,
+    sequence::{delimited, separated_pair, terminated},
+    IResult, Parser,
 };
 
 use crate::uapi::{BootEntry, EntryKey};
@@ -14,22 +15,50 @@ fn is_line_ending(byte: char) -> bool {
     byte == '\r' || byte == '\n'
 }
 
-/// Matches a sequence of non-space characters
+/// Matches a non-empty sequence of non-space characters
 fn non_space(input: &str) -> IResult<&str, &str> {
-    input.split_at_position_complete(char::is_whitespace)
+    take_till1(char::is_whitespace)(input)
 }
 
-/// This entry attribute is a single path
+/// This entry attribute is a single path. Trailing whitespace is not part of the argument.
 fn single_string_argument(input: &str) -> IResult<&str, &str> {
     let (rest, argument) = take_till1(is_line_ending)(input)?;
-    Ok((rest, argument))
+    Ok((rest, argument.trim_end()))
 }
 
-/// This entry attribute is a space-separated list of tokens
+/// This entry attribute is a list of tokens, separated by spaces or tabs
 fn space_separated_list(input: &str) -> IResult<&str, Vec<&str>> {
     separated_list0(space1, non_space)(input)
 }
 
+/// Matches a single option. Double-quoted substrings may contain whitespace, and `\"` is a literal
+/// quote. The quotes themselves are not part of the option, and an unterminated quote is an error.
+fn option(input: &str) -> IResult<&str, String> {
+    let mut option = String::new();
+    let mut quoted = false;
+    let mut end = input.len();
+    let mut chars = input.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|(_, next)| *next == '"') => {
+                chars.next();
+                option.push('"');
+            }
+            '"' => quoted = !quoted,
+            c if is_line_ending(c) || (c.is_whitespace() && !quoted) => {
+                end = index;
+                break;
+            }
+            c => option.push(c),
+        }
+    }
+    match end {
+        0 => Err(nom::Err::Error(Error::new(input, ErrorKind::TakeTill1))),
+        _ if quoted => Err(nom::Err::Failure(Error::new(input, ErrorKind::Char))),
+        _ => Ok((&input[end..], option)),
+    }
+}
+
 /// Parse a "linux" menu entry key and its associated value
 fn linux(input: &str) -> IResult<&str, EntryKey> {
     let (input, (_, path)) =
@@ -44,16 +73,54 @@ fn devicetree(input: &str) -> IResult<&str, EntryKey> {
     Ok((input, EntryKey::Devicetree(path.into())))
 }
 
-/// Parse an "options" menu entry key and its associated value
-fn options(input: &str) -> IResult<&str, EntryKey> {
-    let (input, (_, options)) =
-        separated_pair(tag_no_case("options"), space1, space_separated_list)(input)?;
+/// Parse a "devicetree-overlay" menu entry key and its associated value
+fn devicetree_overlay(input: &str) -> IResult<&str, EntryKey> {
+    let (input, (_, paths)) = separated_pair(
+        tag_no_case("devicetree-overlay"),
+        space1,
+        space_separated_list,
+    )(input)?;
     Ok((
         input,
-        EntryKey::Options(options.into_iter().map(|o| o.to_string()).collect()),
+        EntryKey::DevicetreeOverlay(paths.into_iter().map(|p| p.into()).collect()),
     ))
 }
 
+/// Parse a "version" menu entry key and its associated value
+fn version(input: &str) -> IResult<&str, EntryKey> {
+    let (input, (_, version)) =
+        separated_pair(tag_no_case("version"), space1, single_string_argument)(input)?;
+    Ok((input, EntryKey::Version(version.to_string())))
+}
+
+/// Parse a "machine-id" menu entry key and its associated value
+fn machine_id(input: &str) -> IResult<&str, EntryKey> {
+    let (input, (_, machine_id)) =
+        separated_pair(tag_no_case("machine-id"), space1, single_string_argument)(input)?;
+    Ok((input, EntryKey::MachineId(machine_id.to_string())))
+}
+
+/// Parse an "architecture" menu entry key and its associated value
+fn architecture(input: &str) -> IResult<&str, EntryKey> {
+    let (input, (_, architecture)) =
+        separated_pair(tag_no_case("architecture"), space1, single_string_argument)(input)?;
+    Ok((input, EntryKey::Architecture(architecture.to_string())))
+}
+
+/// Parse an "options" menu entry key and its associated value. Empty options (`""`) are dropped.
+fn options(input: &str) -> IResult<&str, EntryKey> {
+    let (input, (_, options)) = separated_pair(
+        tag_no_case("options"),
+        space1,
+        separated_list0(space1, option),
+    )(input)?;
+    let options = options
+        .into_iter()
+        .filter(|option| !option.is_empty())
+        .collect();
+    Ok((input, EntryKey::Options(options)))
+}
+
 /// Parse a "title" meny entry key and its associated value
 fn title(input: &str) -> IResult<&str, EntryKey> {
     let (input, (_, title)) =
@@ -61,8 +128,21 @@ fn title(input: &str) -> IResult<&str, EntryKey> {
     Ok((input, EntryKey::Title(title.to_string())))
 }
 
+/// Parse a single menu entry key, ignoring any whitespace surrounding it on its line
 pub fn entry_key(input: &str) -> IResult<&str, EntryKey> {
-    linux.or(devicetree).or(options).or(title).parse(input)
+    delimited(
+        space0,
+        linux
+            .or(devicetree_overlay)
+            .or(devicetree)
+            .or(options)
+            .or(title)
+            .or(version)
+            .or(machine_id)
+            .or(architecture),
+        space0,
+    )
+    .parse(input)
 }
 
 pub fn boot_entry(input: &str) -> IResult<&str, BootEntry> {
@@ -76,7 +156,7 @@ pub fn boot_entry(input: &str) -> IResult<&str, BootEntry> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::cell::LazyCell;
+    use std::sync::LazyLock;
 
     #[test]
     fn linux_entry() {
@@ -90,6 +170,15 @@ mod test {
         assert_eq!(entry, EntryKey::Devicetree("/boot.dtb".into()));
     }
 
+    #[test]
+    fn devicetree_overlay_entry() {
+        let (_, entry) = entry_key("devicetree-overlay /a.dtbo /b.dtbo").unwrap();
+        assert_eq!(
+            entry,
+            EntryKey::DevicetreeOverlay(vec!["/a.dtbo".into(), "/b.dtbo".into()])
+        );
+    }
+
     #[test]
     fn options_entry() {
         let (_, entry) =
@@ -105,12 +194,110 @@ mod test {
         );
     }
 
+    #[test]
+    fn quoted_options() {
+        let (rest, entry) =
+            entry_key("options init=\"/usr/lib/systemd systemd\" \"quiet\" ro").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            entry,
+            EntryKey::Options(vec![
+                "init=/usr/lib/systemd systemd".into(),
+                "quiet".into(),
+                "ro".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn escaped_quote_option() {
+        let (_, entry) = entry_key(r#"options motd="say \"hi\"" a\"b"#).unwrap();
+        assert_eq!(
+            entry,
+            EntryKey::Options(vec!["motd=say \"hi\"".into(), "a\"b".into()])
+        );
+    }
+
+    #[test]
+    fn unterminated_quote_option() {
+        assert!(matches!(
+            entry_key("options init=\"/usr/lib/systemd quiet"),
+            Err(nom::Err::Failure(_))
+        ));
+        assert!(boot_entry("linux /Image\noptions \"quiet\nversion 6.1\n").is_err());
+    }
+
+    #[test]
+    fn empty_option() {
+        let (rest, entry) = entry_key("options \"\" quiet \"\"").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(entry, EntryKey::Options(vec!["quiet".into()]));
+    }
+
     #[test]
     fn title_entry() {
         let (_, entry) = entry_key("title Fedora 19 (Rawhide)").unwrap();
         assert_eq!(entry, EntryKey::Title("Fedora 19 (Rawhide)".to_string()));
     }
 
+    #[test]
+    fn tab_separated_entry() {
+        let (_, entry) = entry_key("linux\t\t/Image").unwrap();
+        assert_eq!(entry, EntryKey::Linux("/Image".into()));
+    }
+
+    #[test]
+    fn tab_separated_options() {
+        let (_, entry) = entry_key("options\troot=/dev/nfs \tquiet\tro").unwrap();
+        assert_eq!(
+            entry,
+            EntryKey::Options(vec!["root=/dev/nfs".into(), "quiet".into(), "ro".into()])
+        );
+    }
+
+    #[test]
+    fn indented_entry() {
+        let (rest, entry) = boot_entry("  linux /Image\n\toptions quiet ro \n").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            entry,
+            BootEntry {
+                keys: vec![
+                    EntryKey::Linux("/Image".into()),
+                    EntryKey::Options(vec!["quiet".into(), "ro".into()]),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn title_trailing_spaces() {
+        let (rest, entry) = entry_key("title  Fedora 19  ").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(entry, EntryKey::Title("Fedora 19".to_string()));
+    }
+
+    #[test]
+    fn version_entry() {
+        let (_, entry) = entry_key("version 6.1.0-rc1").unwrap();
+        assert_eq!(entry, EntryKey::Version("6.1.0-rc1".to_string()));
+    }
+
+    #[test]
+    fn machine_id_entry() {
+        let (_, entry) = entry_key("machine-id 6a9857a393724b7a981ebb5b8495b9ea").unwrap();
+        assert_eq!(
+            entry,
+            EntryKey::MachineId("6a9857a393724b7a981ebb5b8495b9ea".to_string())
+        );
+    }
+
+    #[test]
+    fn architecture_entry() {
+        let (_, entry) = entry_key("architecture X64").unwrap();
+        assert_eq!(entry, EntryKey::Architecture("X64".to_string()));
+    }
+
     #[test]
     fn single_erroneous_entry() {
         let (input, entry) = boot_entry("foo /bar\n").unwrap();
@@ -208,7 +395,7 @@ mod test {
         );
     }
 
-    const COMPLETE_TEST: LazyCell<Vec<String>> = LazyCell::new(|| {
+    static COMPLETE_TEST: LazyLock<Vec<String>> = LazyLock::new(|| {
         vec![
             "title Fedora 19 (Rawhide)".to_string(),
             "linux /Image".to_string(),
@@ -217,7 +404,7 @@ mod test {
         ]
     });
 
-    const COMPLETE_RESULT: LazyCell<BootEntry> = LazyCell::new(|| BootEntry {
+    static COMPLETE_RESULT: LazyLock<BootEntry> = LazyLock::new(|| BootEntry {
         keys: vec![
             EntryKey::Title("Fedora 19 (Rawhide)".into()),
             EntryKey::Linux("/Image".into()),
@@ -1,51 +1,147 @@
 use core::fmt;
-use std::path::PathBuf;
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
 
 use crate::{uapi, BootFile};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ConfigurationConversionError;
+/// The reason a boot entry could not be converted to a syslinux configuration
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, thiserror::Error)]
+pub enum ConfigurationConversionError {
+    #[error("boot entry has no title")]
+    MissingTitle,
+    #[error("boot entry has no linux key")]
+    MissingKernel,
+    #[error("boot entry key \"{0}\" has no equivalent label directive")]
+    UnsupportedKey(String),
+    #[error("boot entry {index}")]
+    InvalidEntry {
+        /// The position of the failing entry in the input
+        index: usize,
+        source: Box<ConfigurationConversionError>,
+    },
+}
+
+/// Adapts a [fmt::Formatter] to [io::Write], so that the [fmt::Display] implementations can share
+/// the streaming serializer.
+struct FormatterWriter<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl io::Write for FormatterWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = std::str::from_utf8(buf)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        self.0
+            .write_str(text)
+            .map_err(|_| io::Error::other("formatter error"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Format a value through its streaming serializer
+fn display_with(
+    f: &mut fmt::Formatter<'_>,
+    write: impl FnOnce(&mut FormatterWriter<'_, '_>) -> io::Result<()>,
+) -> fmt::Result {
+    write(&mut FormatterWriter(f)).map_err(|_| fmt::Error)
+}
 
 /// A KERNEL-LIKE Directive, specifying the image to boot
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Deserialize, ::serde::Serialize))]
 pub enum Kernel {
     Kernel(PathBuf),
+    /// Boot from a local disk instead of the network. The value selects the boot method, where
+    /// 0 performs a normal boot from the next device.
+    LocalBoot(i32),
 }
 
-impl fmt::Display for Kernel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Kernel {
+    fn write_to(&self, f: &mut impl io::Write) -> io::Result<()> {
         match self {
             Kernel::Kernel(image) => write!(f, "KERNEL {}", image.display()),
+            Kernel::LocalBoot(kind) => write!(f, "LOCALBOOT {}", kind),
         }
     }
 }
 
+impl fmt::Display for Kernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        display_with(f, |writer| self.write_to(writer))
+    }
+}
+
 impl BootFile for Kernel {
-    fn boot_file(&self) -> Option<&std::path::Path> {
+    fn boot_files(&self) -> Vec<&Path> {
         match self {
-            Kernel::Kernel(image) => Some(image),
+            Kernel::Kernel(image) => vec![image],
+            Kernel::LocalBoot(_) => vec![],
+        }
+    }
+
+    fn boot_files_mut(&mut self) -> Vec<&mut PathBuf> {
+        match self {
+            Kernel::Kernel(image) => vec![image],
+            Kernel::LocalBoot(_) => vec![],
         }
     }
 }
 
 /// Directives that configure a boot label
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Deserialize, ::serde::Serialize))]
 pub enum LabelDirective {
     /// An initial ramdisk
     Initrd(PathBuf),
     /// A device tree blob
     Fdt(PathBuf),
+    /// Device tree overlays, applied in order on top of the device tree blob
+    FdtOverlays(Vec<PathBuf>),
+    /// A directory of device tree blobs, from which U-Boot chooses one based on the board. Boot
+    /// entries have no equivalent key, so this is never produced from a [uapi::BootEntry].
+    FdtDir(PathBuf),
+    /// Append information about the boot interface to the kernel command line. With 1, an `ip=`
+    /// parameter describing the PXE-assigned address. With 2, `BOOTIF=` naming the boot MAC.
+    IpAppend(u8),
     // TODO: The Append option is actually a "dual-purpose" directive, not a "label directive"
     /// Kernel configuration options
     Append(Vec<String>),
+    /// The name shown for the label in the menu, which unlike the label name may contain any
+    /// characters
+    MenuLabel(String),
 }
 
 impl BootFile for LabelDirective {
-    fn boot_file(&self) -> Option<&std::path::Path> {
+    fn boot_files(&self) -> Vec<&Path> {
+        match self {
+            LabelDirective::Initrd(initrd) => vec![initrd],
+            LabelDirective::Fdt(fdt) => vec![fdt],
+            LabelDirective::FdtOverlays(overlays) => {
+                overlays.iter().map(PathBuf::as_path).collect()
+            }
+            // The directory itself is reported, so that it is rebased along with the other boot
+            // files. Servers must serve the files inside it rather than the path itself.
+            LabelDirective::FdtDir(directory) => vec![directory],
+            LabelDirective::IpAppend(_)
+            | LabelDirective::Append(_)
+            | LabelDirective::MenuLabel(_) => vec![],
+        }
+    }
+
+    fn boot_files_mut(&mut self) -> Vec<&mut PathBuf> {
         match self {
-            LabelDirective::Initrd(initrd) => Some(initrd),
-            LabelDirective::Fdt(fdt) => Some(fdt),
-            LabelDirective::Append(_) => None,
+            LabelDirective::Initrd(initrd) => vec![initrd],
+            LabelDirective::Fdt(fdt) => vec![fdt],
+            LabelDirective::FdtOverlays(overlays) => overlays.iter_mut().collect(),
+            LabelDirective::FdtDir(directory) => vec![directory],
+            LabelDirective::IpAppend(_)
+            | LabelDirective::Append(_)
+            | LabelDirective::MenuLabel(_) => vec![],
         }
     }
 }
@@ -54,26 +150,61 @@ impl TryFrom<uapi::EntryKey> for LabelDirective {
     type Error = ConfigurationConversionError;
     fn try_from(value: uapi::EntryKey) -> Result<Self, Self::Error> {
         match value {
-            uapi::EntryKey::Title(_) => Err(ConfigurationConversionError),
-            uapi::EntryKey::Linux(_) => Err(ConfigurationConversionError),
+            uapi::EntryKey::Title(_) => Err(ConfigurationConversionError::UnsupportedKey(
+                "title".to_string(),
+            )),
+            uapi::EntryKey::Linux(_) => Err(ConfigurationConversionError::UnsupportedKey(
+                "linux".to_string(),
+            )),
             uapi::EntryKey::Devicetree(fdt) => Ok(LabelDirective::Fdt(fdt)),
+            uapi::EntryKey::DevicetreeOverlay(overlays) => {
+                Ok(LabelDirective::FdtOverlays(overlays))
+            }
             uapi::EntryKey::Options(options) => Ok(LabelDirective::Append(options)),
+            uapi::EntryKey::Version(_) => Err(ConfigurationConversionError::UnsupportedKey(
+                "version".to_string(),
+            )),
+            uapi::EntryKey::MachineId(_) => Err(ConfigurationConversionError::UnsupportedKey(
+                "machine-id".to_string(),
+            )),
+            uapi::EntryKey::Architecture(_) => Err(ConfigurationConversionError::UnsupportedKey(
+                "architecture".to_string(),
+            )),
         }
     }
 }
 
-impl fmt::Display for LabelDirective {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl LabelDirective {
+    fn write_to(&self, f: &mut impl io::Write) -> io::Result<()> {
         match self {
             LabelDirective::Initrd(initrd) => write!(f, "INITRD {}", initrd.display()),
             LabelDirective::Fdt(fdt) => write!(f, "FDT {}", fdt.display()),
-            LabelDirective::Append(options) => write!(f, "APPEND {}", options.join(" ")),
+            LabelDirective::FdtOverlays(overlays) => {
+                write!(f, "FDTOVERLAYS")?;
+                for overlay in overlays {
+                    write!(f, " {}", overlay.display())?;
+                }
+                Ok(())
+            }
+            LabelDirective::FdtDir(directory) => write!(f, "FDTDIR {}", directory.display()),
+            LabelDirective::IpAppend(flags) => write!(f, "IPAPPEND {}", flags),
+            LabelDirective::Append(options) => {
+                write!(f, "APPEND {}", uapi::join_options(options))
+            }
+            LabelDirective::MenuLabel(title) => write!(f, "MENU LABEL {}", title),
         }
     }
 }
 
+impl fmt::Display for LabelDirective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        display_with(f, |writer| self.write_to(writer))
+    }
+}
+
 /// A label clause
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Deserialize, ::serde::Serialize))]
 pub struct Label {
     pub name: String,
     pub kernel: Kernel,
@@ -82,27 +213,197 @@ pub struct Label {
 
 impl fmt::Display for Label {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "LABEL {}\n", self.name)?;
-        self.kernel.fmt(f)?;
-        write!(f, "\n")?;
+        display_with(f, |writer| self.write_to(writer))
+    }
+}
+
+impl Label {
+    /// Write the label to `writer`, as [fmt::Display] would format it.
+    pub fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "LABEL {}", self.name)?;
+        self.kernel.write_to(writer)?;
+        writeln!(writer)?;
         for directive in &self.directives {
-            directive.fmt(f)?;
-            write!(f, "\n")?;
+            directive.write_to(writer)?;
+            writeln!(writer)?;
         }
         Ok(())
     }
+
+    /// The name shown for this label in the menu: its `MENU LABEL`, or its name if it has none.
+    pub fn title(&self) -> &str {
+        self.directives
+            .iter()
+            .find_map(|directive| match directive {
+                LabelDirective::MenuLabel(title) => Some(title.as_str()),
+                _ => None,
+            })
+            .unwrap_or(&self.name)
+    }
+
+    /// The files referenced by this label: the kernel, initrd, device tree, etc.
+    pub fn boot_files(&self) -> Vec<PathBuf> {
+        self.directives
+            .iter()
+            .flat_map(|directive| directive.boot_files())
+            .chain(self.kernel.boot_files())
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    /// Move each file referenced by this label that is within `from` to the same place in `to`.
+    /// For example, rebasing from `/boot` to `/` changes `/boot/vmlinuz` to `/vmlinuz`. Files
+    /// outside of `from` are unchanged.
+    pub fn rebase_boot_files(&mut self, from: &Path, to: &Path) {
+        let files = self
+            .directives
+            .iter_mut()
+            .flat_map(|directive| directive.boot_files_mut())
+            .chain(self.kernel.boot_files_mut());
+        for file in files {
+            if let Ok(relative) = file.strip_prefix(from) {
+                *file = to.join(relative);
+            }
+        }
+    }
+
+    /// Compare two labels, ignoring the order of kernel parameters in `APPEND` directives (except
+    /// for order-sensitive parameters, such as `console=`). Unlike [PartialEq], `quiet ro` and `ro
+    /// quiet` are equal.
+    pub fn semantically_eq(&self, other: &Label) -> bool {
+        let directive_eq = |a: &LabelDirective, b: &LabelDirective| match (a, b) {
+            (LabelDirective::Append(a), LabelDirective::Append(b)) => {
+                uapi::normalize_options(a) == uapi::normalize_options(b)
+            }
+            (a, b) => a == b,
+        };
+        self.name == other.name
+            && self.kernel == other.kernel
+            && self.directives.len() == other.directives.len()
+            && self
+                .directives
+                .iter()
+                .zip(&other.directives)
+                .all(|(a, b)| directive_eq(a, b))
+    }
 }
 
 /// A Syslinux configuration
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Deserialize, ::serde::Serialize))]
 pub struct Configuration {
+    /// The name of the label booted by default
+    pub default: Option<String>,
+    /// The title shown at the top of the menu
+    pub menu_title: Option<String>,
     pub labels: Vec<Label>,
 }
 
+impl From<Label> for Configuration {
+    fn from(value: Label) -> Self {
+        Configuration {
+            default: None,
+            menu_title: None,
+            labels: vec![value],
+        }
+    }
+}
+
+impl Configuration {
+    /// Write the configuration to `writer` one label at a time, as [fmt::Display] would format
+    /// it.
+    pub fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        if let Some(default) = &self.default {
+            writeln!(writer, "DEFAULT {}", default)?;
+        }
+        if let Some(title) = &self.menu_title {
+            writeln!(writer, "MENU TITLE {}", title)?;
+        }
+        for label in &self.labels {
+            label.write_to(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Convert a collection of boot entries into a configuration with one label per entry,
+    /// failing on the first entry that cannot be converted.
+    pub fn try_from_entries(
+        entries: impl IntoIterator<Item = uapi::BootEntry>,
+    ) -> Result<Configuration, ConfigurationConversionError> {
+        let labels = entries
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                Label::try_from(entry).map_err(|source| {
+                    ConfigurationConversionError::InvalidEntry {
+                        index,
+                        source: Box::new(source),
+                    }
+                })
+            })
+            .collect::<Result<Vec<Label>, _>>()?;
+        Ok(Configuration {
+            default: None,
+            menu_title: None,
+            labels,
+        })
+    }
+}
+
+impl fmt::Display for Configuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        display_with(f, |writer| self.write_to(writer))
+    }
+}
+
 // TODO: We probably care more about morphing Configurations than individual BootEntry/Label(s).
+/// The name of the label for a boot entry with this title. Label names are single words, so runs
+/// of other characters (e.g. spaces) become a hyphen.
+fn label_name(title: &str) -> String {
+    let is_name_character = |c: char| c.is_ascii_alphanumeric() || "-_.+".contains(c);
+    let name = title
+        .split(|c| !is_name_character(c))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    match name.is_empty() {
+        true => "label".to_string(),
+        false => name,
+    }
+}
+
+/// Rename each label with the same name as an earlier one, by appending the first of `-2`, `-3`,
+/// etc. that no other label is named. Renamed labels are still shown by their original title.
+pub fn make_names_unique(labels: &mut [Label]) {
+    let mut taken = labels
+        .iter()
+        .map(|label| label.name.clone())
+        .collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+    for label in labels {
+        if seen.insert(label.name.clone()) {
+            continue;
+        }
+        // INVARIANT: There are only finitely many names taken
+        let name = (2..)
+            .map(|suffix| format!("{}-{}", label.name, suffix))
+            .find(|name| !taken.contains(name))
+            .unwrap();
+        if label.title() == label.name {
+            label
+                .directives
+                .insert(0, LabelDirective::MenuLabel(label.name.clone()));
+        }
+        taken.insert(name.clone());
+        seen.insert(name.clone());
+        label.name = name;
+    }
+}
+
 impl TryFrom<uapi::BootEntry> for Label {
     type Error = ConfigurationConversionError;
     fn try_from(value: uapi::BootEntry) -> Result<Self, Self::Error> {
-        let mut name: Option<String> = None;
+        let mut title: Option<String> = None;
         let mut kernel: Option<PathBuf> = None;
         let directives = value
             .keys
@@ -110,8 +411,8 @@ impl TryFrom<uapi::BootEntry> for Label {
             // TODO: The use of filter_map in TryFrom<BootEntry> will discard all invalid entries.
             // Is that really what we want?
             .filter_map(|key| match key {
-                uapi::EntryKey::Title(title) => {
-                    name = Some(title);
+                uapi::EntryKey::Title(value) => {
+                    title = Some(value);
                     None
                 }
                 uapi::EntryKey::Linux(linux) => {
@@ -122,8 +423,13 @@ impl TryFrom<uapi::BootEntry> for Label {
             })
             .collect::<Vec<LabelDirective>>();
 
-        let name = name.ok_or(ConfigurationConversionError)?;
-        let kernel = Kernel::Kernel(kernel.ok_or(ConfigurationConversionError)?);
+        let title = title.ok_or(ConfigurationConversionError::MissingTitle)?;
+        let kernel = Kernel::Kernel(kernel.ok_or(ConfigurationConversionError::MissingKernel)?);
+        let name = label_name(&title);
+        let mut directives = directives;
+        if name != title {
+            directives.insert(0, LabelDirective::MenuLabel(title));
+        }
         Ok(Label {
             name,
             kernel,
@@ -134,8 +440,70 @@ impl TryFrom<uapi::BootEntry> for Label {
 
 #[cfg(test)]
 mod test {
-    use super::{Kernel, Label};
+    use super::{
+        label_name, make_names_unique, Configuration, ConfigurationConversionError, Kernel, Label,
+        LabelDirective,
+    };
     use crate::uapi;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn menu_label() {
+        assert_eq!(label_name("Fedora"), "Fedora");
+        assert_eq!(label_name("Fedora 19 (Rawhide)"), "Fedora-19-Rawhide");
+        assert_eq!(label_name("Linux 6.1.0-rc1+"), "Linux-6.1.0-rc1+");
+        assert_eq!(label_name("???"), "label");
+
+        let entry: uapi::BootEntry = "title Fedora 19\nlinux /Image\n".parse().unwrap();
+        let label = Label::try_from(entry).unwrap();
+        assert_eq!(label.title(), "Fedora 19");
+        let configuration = Configuration {
+            default: Some(label.name.clone()),
+            menu_title: Some("Lab machines".to_string()),
+            labels: vec![label],
+        };
+        assert_eq!(
+            configuration.to_string(),
+            "DEFAULT Fedora-19\nMENU TITLE Lab machines\nLABEL Fedora-19\nKERNEL /Image\nMENU LABEL Fedora 19\n"
+        );
+
+        // Labels without a MENU LABEL are shown by their name
+        let entry: uapi::BootEntry = "title Fedora\nlinux /Image\n".parse().unwrap();
+        let label = Label::try_from(entry).unwrap();
+        assert!(label.directives.is_empty());
+        assert_eq!(label.title(), "Fedora");
+    }
+
+    #[test]
+    fn unique_names() {
+        let label = |title: &str| {
+            let entry: uapi::BootEntry =
+                format!("title {}\nlinux /Image\n", title).parse().unwrap();
+            Label::try_from(entry).unwrap()
+        };
+        let mut labels = vec![
+            label("Fedora"),
+            label("Fedora"),
+            label("Fedora 19"),
+            label("Fedora-19"),
+            label("Fedora-2"),
+        ];
+        make_names_unique(&mut labels);
+        let names = labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.title()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                ("Fedora", "Fedora"),
+                ("Fedora-3", "Fedora"),
+                ("Fedora-19", "Fedora 19"),
+                ("Fedora-19-2", "Fedora-19"),
+                ("Fedora-2", "Fedora-2"),
+            ]
+        );
+    }
 
     #[test]
     fn valid_syslinux_from_uapi() {
@@ -150,10 +518,287 @@ mod test {
         assert_eq!(
             result,
             Label {
-                name: "Fedora 19 (Rawhide)".to_string(),
+                name: "Fedora-19-Rawhide".to_string(),
                 kernel: Kernel::Kernel("/Image".into()),
-                directives: vec![],
+                directives: vec![LabelDirective::MenuLabel("Fedora 19 (Rawhide)".to_string())],
+            }
+        );
+    }
+
+    #[test]
+    fn fdt_overlays_from_uapi() {
+        let entry: uapi::BootEntry =
+            "title Fedora\nlinux /Image\ndevicetree /boot.dtb\ndevicetree-overlay /a.dtbo /b.dtbo\n"
+                .parse()
+                .unwrap();
+        let label: Label = entry.try_into().unwrap();
+        assert_eq!(
+            label.to_string(),
+            "LABEL Fedora\nKERNEL /Image\nFDT /boot.dtb\nFDTOVERLAYS /a.dtbo /b.dtbo\n"
+        );
+        assert_eq!(
+            label.boot_files(),
+            vec![
+                PathBuf::from("/boot.dtb"),
+                PathBuf::from("/a.dtbo"),
+                PathBuf::from("/b.dtbo"),
+                PathBuf::from("/Image"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebase_boot_files() {
+        let mut label = Label {
+            name: "Fedora".to_string(),
+            kernel: Kernel::Kernel("/boot/vmlinuz".into()),
+            directives: vec![
+                LabelDirective::Initrd("/boot/initrd.img".into()),
+                LabelDirective::Append(vec!["/boot/quiet".to_string()]),
+                LabelDirective::Fdt("/dtbs/board.dtb".into()),
+            ],
+        };
+        label.rebase_boot_files(Path::new("/boot"), Path::new("/"));
+        assert_eq!(
+            label.boot_files(),
+            vec![
+                PathBuf::from("/initrd.img"),
+                PathBuf::from("/dtbs/board.dtb"),
+                PathBuf::from("/vmlinuz"),
+            ]
+        );
+        assert_eq!(
+            label.directives[1],
+            LabelDirective::Append(vec!["/boot/quiet".to_string()])
+        );
+    }
+
+    #[test]
+    fn configuration_display() {
+        let label = |name: &str| Label {
+            name: name.to_string(),
+            kernel: Kernel::Kernel(format!("/{}/Image", name).into()),
+            directives: vec![LabelDirective::Append(vec!["quiet".to_string()])],
+        };
+        let configuration = Configuration {
+            default: Some("rescue".to_string()),
+            menu_title: None,
+            labels: vec![label("linux"), label("rescue")],
+        };
+        assert_eq!(
+            configuration.to_string(),
+            "DEFAULT rescue\nLABEL linux\nKERNEL /linux/Image\nAPPEND quiet\nLABEL rescue\nKERNEL /rescue/Image\nAPPEND quiet\n"
+        );
+    }
+
+    #[test]
+    fn semantically_eq() {
+        let label = |options: &[&str]| Label {
+            name: "Fedora 19 (Rawhide)".to_string(),
+            kernel: Kernel::Kernel("/Image".into()),
+            directives: vec![
+                LabelDirective::Fdt("/boot.dtb".into()),
+                LabelDirective::Append(options.iter().map(|s| s.to_string()).collect()),
+            ],
+        };
+        assert!(label(&["quiet", "ro"]).semantically_eq(&label(&["ro", "quiet"])));
+        assert!(!label(&["quiet", "ro"]).semantically_eq(&label(&["ro"])));
+        assert!(!label(&["console=ttyS0", "console=tty0"])
+            .semantically_eq(&label(&["console=tty0", "console=ttyS0"])));
+    }
+
+    #[test]
+    fn syslinux_from_uapi_missing_title() {
+        let configuration = uapi::BootEntry {
+            keys: vec![uapi::EntryKey::Linux("/Image".into())],
+        };
+        assert_eq!(
+            Label::try_from(configuration),
+            Err(ConfigurationConversionError::MissingTitle)
+        );
+    }
+
+    #[test]
+    fn syslinux_from_uapi_missing_kernel() {
+        let configuration = uapi::BootEntry {
+            keys: vec![
+                uapi::EntryKey::Title("Fedora 19 (Rawhide)".to_string()),
+                uapi::EntryKey::Devicetree("/boot.dtb".into()),
+            ],
+        };
+        assert_eq!(
+            Label::try_from(configuration),
+            Err(ConfigurationConversionError::MissingKernel)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let label = Label {
+            name: "Fedora".to_string(),
+            kernel: Kernel::Kernel("/Image".into()),
+            directives: vec![
+                LabelDirective::FdtOverlays(vec!["/a.dtbo".into(), "/b.dtbo".into()]),
+                LabelDirective::Append(vec!["quiet".to_string(), "ro".to_string()]),
+            ],
+        };
+        let json = serde_json::to_string(&label).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"Fedora","kernel":{"Kernel":"/Image"},"directives":[{"FdtOverlays":["/a.dtbo","/b.dtbo"]},{"Append":["quiet","ro"]}]}"#
+        );
+        assert_eq!(serde_json::from_str::<Label>(&json).unwrap(), label);
+
+        let configuration = Configuration {
+            default: Some("Fedora".to_string()),
+            menu_title: None,
+            labels: vec![label],
+        };
+        let json = serde_json::to_string(&configuration).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Configuration>(&json).unwrap(),
+            configuration
+        );
+    }
+
+    #[test]
+    fn write_to() {
+        let configuration = Configuration {
+            default: Some("a".to_string()),
+            menu_title: None,
+            labels: (0..100)
+                .map(|index| Label {
+                    name: format!("label{}", index),
+                    kernel: Kernel::Kernel(format!("/{}/Image", index).into()),
+                    directives: vec![
+                        LabelDirective::FdtOverlays(vec!["/a.dtbo".into(), "/b.dtbo".into()]),
+                        LabelDirective::Append(vec!["quiet".to_string(), "ro".to_string()]),
+                    ],
+                })
+                .collect(),
+        };
+        let mut output = Vec::new();
+        configuration.write_to(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            configuration.to_string()
+        );
+
+        let mut output = Vec::new();
+        configuration.labels[1].write_to(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            configuration.labels[1].to_string()
+        );
+    }
+
+    #[test]
+    fn local_boot() {
+        let configuration = Configuration {
+            default: Some("Fedora".to_string()),
+            menu_title: None,
+            labels: vec![
+                Label {
+                    name: "Fedora".to_string(),
+                    kernel: Kernel::Kernel("/Image".into()),
+                    directives: vec![],
+                },
+                Label {
+                    name: "local".to_string(),
+                    kernel: Kernel::LocalBoot(0),
+                    directives: vec![],
+                },
+            ],
+        };
+        assert_eq!(
+            configuration.to_string(),
+            "DEFAULT Fedora\nLABEL Fedora\nKERNEL /Image\nLABEL local\nLOCALBOOT 0\n"
+        );
+        assert!(configuration.labels[1].boot_files().is_empty());
+    }
+
+    #[test]
+    fn ip_append() {
+        let label = Label {
+            name: "Fedora".to_string(),
+            kernel: Kernel::Kernel("/Image".into()),
+            directives: vec![
+                LabelDirective::Append(vec!["quiet".to_string()]),
+                LabelDirective::IpAppend(2),
+            ],
+        };
+        assert_eq!(
+            label.to_string(),
+            "LABEL Fedora\nKERNEL /Image\nAPPEND quiet\nIPAPPEND 2\n"
+        );
+        assert_eq!(label.boot_files(), vec![PathBuf::from("/Image")]);
+    }
+
+    #[test]
+    fn fdt_dir() {
+        let mut label = Label {
+            name: "Fedora".to_string(),
+            kernel: Kernel::Kernel("/boot/Image".into()),
+            directives: vec![LabelDirective::FdtDir("/boot/dtbs".into())],
+        };
+        assert_eq!(
+            label.to_string(),
+            "LABEL Fedora\nKERNEL /boot/Image\nFDTDIR /boot/dtbs\n"
+        );
+
+        label.rebase_boot_files(Path::new("/boot"), Path::new("/"));
+        assert_eq!(
+            label.boot_files(),
+            vec![PathBuf::from("/dtbs"), PathBuf::from("/Image")]
+        );
+        assert_eq!(
+            label.to_string(),
+            "LABEL Fedora\nKERNEL /Image\nFDTDIR /dtbs\n"
+        );
+    }
+
+    #[test]
+    fn configuration_from_entries() {
+        let entry = |text: &str| text.parse::<uapi::BootEntry>().unwrap();
+        let configuration = Configuration::try_from_entries([
+            entry("title A\nlinux /A\n"),
+            entry("title B\nlinux /B\n"),
+        ])
+        .unwrap();
+        assert_eq!(
+            configuration.to_string(),
+            "LABEL A\nKERNEL /A\nLABEL B\nKERNEL /B\n"
+        );
+
+        let error = Configuration::try_from_entries([
+            entry("title A\nlinux /A\n"),
+            entry("title B\n"),
+            entry("title C\nlinux /C\n"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error,
+            ConfigurationConversionError::InvalidEntry {
+                index: 1,
+                source: Box::new(ConfigurationConversionError::MissingKernel),
             }
         );
+        // The cause is the source, rather than part of the message, so it isn't printed twice
+        assert_eq!(error.to_string(), "boot entry 1");
+        assert_eq!(
+            std::error::Error::source(&error).unwrap().to_string(),
+            "boot entry has no linux key"
+        );
+    }
+
+    #[test]
+    fn unsupported_label_directive() {
+        assert_eq!(
+            LabelDirective::try_from(uapi::EntryKey::Linux("/Image".into())),
+            Err(ConfigurationConversionError::UnsupportedKey(
+                "linux".to_string()
+            ))
+        );
     }
 }
@@ -1,5 +1,7 @@
 use core::fmt;
 use std::{
+    cmp::Ordering,
+    mem,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -13,18 +15,62 @@ pub enum EntryKey {
     Title(String),
     Linux(PathBuf),
     Devicetree(PathBuf),
+    /// Device tree overlays, applied in order on top of the device tree
+    DevicetreeOverlay(Vec<PathBuf>),
     Options(Vec<String>),
+    /// The version of the operating system, used to sort entries
+    Version(String),
+    /// The machine ID of the installation, used to group entries
+    MachineId(String),
+    /// The architecture the entry boots on, as an EFI architecture name (e.g. `x64`, `aa64`)
+    Architecture(String),
 }
 
 impl crate::BootFile for EntryKey {
-    fn boot_file(&self) -> Option<&Path> {
+    fn boot_files(&self) -> Vec<&Path> {
         match self {
-            EntryKey::Linux(path) => Some(path),
-            EntryKey::Devicetree(path) => Some(path),
-            EntryKey::Options(_) => None,
-            EntryKey::Title(_) => None,
+            EntryKey::Linux(path) => vec![path],
+            EntryKey::Devicetree(path) => vec![path],
+            EntryKey::DevicetreeOverlay(paths) => paths.iter().map(PathBuf::as_path).collect(),
+            EntryKey::Options(_) => vec![],
+            EntryKey::Title(_) => vec![],
+            EntryKey::Version(_) => vec![],
+            EntryKey::MachineId(_) => vec![],
+            EntryKey::Architecture(_) => vec![],
         }
     }
+
+    fn boot_files_mut(&mut self) -> Vec<&mut PathBuf> {
+        match self {
+            EntryKey::Linux(path) => vec![path],
+            EntryKey::Devicetree(path) => vec![path],
+            EntryKey::DevicetreeOverlay(paths) => paths.iter_mut().collect(),
+            EntryKey::Options(_) => vec![],
+            EntryKey::Title(_) => vec![],
+            EntryKey::Version(_) => vec![],
+            EntryKey::MachineId(_) => vec![],
+            EntryKey::Architecture(_) => vec![],
+        }
+    }
+}
+
+/// Join options into a command line, quoting the value of options that contain whitespace or
+/// quotes, so that it parses back into the same options.
+pub fn join_options(options: &[String]) -> String {
+    let quote = |value: &str| format!("\"{}\"", value.replace('"', "\\\""));
+    options
+        .iter()
+        .map(|option| {
+            if !option.contains(|c: char| c.is_whitespace() || c == '"') {
+                return option.clone();
+            }
+            match option.split_once('=') {
+                Some((key, value)) => format!("{}={}", key, quote(value)),
+                None => quote(option),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 impl fmt::Display for EntryKey {
@@ -32,8 +78,18 @@ impl fmt::Display for EntryKey {
         match self {
             EntryKey::Linux(path) => write!(f, "linux {}", path.display()),
             EntryKey::Devicetree(path) => write!(f, "devicetree {}", path.display()),
-            EntryKey::Options(options) => write!(f, "options {}", options.join(" ")),
+            EntryKey::DevicetreeOverlay(paths) => {
+                write!(f, "devicetree-overlay")?;
+                for path in paths {
+                    write!(f, " {}", path.display())?;
+                }
+                Ok(())
+            }
+            EntryKey::Options(options) => write!(f, "options {}", join_options(options)),
             EntryKey::Title(title) => write!(f, "title {}", title),
+            EntryKey::Version(version) => write!(f, "version {}", version),
+            EntryKey::MachineId(machine_id) => write!(f, "machine-id {}", machine_id),
+            EntryKey::Architecture(architecture) => write!(f, "architecture {}", architecture),
         }
     }
 }
@@ -61,11 +117,180 @@ pub struct BootEntry {
     pub keys: Vec<EntryKey>,
 }
 
+/// Kernel parameters which may be repeated, and for which the order of occurrence is significant.
+/// For example, the last `console=` parameter becomes `/dev/console`.
+const ORDER_SENSITIVE_OPTIONS: &[&str] = &["console"];
+
+/// Compare two kernel parameters for the purposes of normalization. Parameters are ordered by their
+/// full text, except that order-sensitive parameters of the same name compare equal.
+fn compare_options(a: &str, b: &str) -> Ordering {
+    let a_name = a.split('=').next().unwrap_or(a);
+    let b_name = b.split('=').next().unwrap_or(b);
+    if a_name == b_name && ORDER_SENSITIVE_OPTIONS.contains(&a_name) {
+        Ordering::Equal
+    } else {
+        a.cmp(b)
+    }
+}
+
+/// Sort kernel parameters, preserving the relative order of order-sensitive parameters. Anything
+/// following `--` is passed to init, and is left untouched.
+pub(crate) fn normalize_options(options: &[String]) -> Vec<String> {
+    let split = options
+        .iter()
+        .position(|option| option == "--")
+        .unwrap_or(options.len());
+    let (kernel, init) = options.split_at(split);
+    let mut kernel = kernel.to_vec();
+    kernel.sort_by(|a, b| compare_options(a, b));
+    kernel.extend_from_slice(init);
+    kernel
+}
+
+/// Split off the leading run of digits (or non-digits) from a version string
+fn version_segment(version: &str, digits: bool) -> (&str, &str) {
+    let end = version
+        .find(|c: char| c.is_ascii_digit() != digits)
+        .unwrap_or(version.len());
+    version.split_at(end)
+}
+
+/// Whether the rest of a version, after the part it shares with another, marks a pre-release
+fn is_pre_release(suffix: char) -> bool {
+    suffix == '-' || suffix == '~'
+}
+
+/// Compare two version strings. Runs of digits are compared numerically, everything else is
+/// compared lexically, so `6.10` is newer than `6.9`. As with rpm, a `~` suffix is older than
+/// anything in its place, so `6.1~rc1` is older than `6.1`. So is a `-` suffix of a version that is
+/// otherwise the same, so `6.1-rc1` is older than `6.1`.
+pub(crate) fn compare_versions(mut a: &str, mut b: &str) -> Ordering {
+    loop {
+        let (a_digits, b_digits) = match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(b)) if is_pre_release(b) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(a), None) if is_pre_release(a) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some('~'), Some('~')) => {
+                (a, b) = (&a[1..], &b[1..]);
+                continue;
+            }
+            (Some('~'), Some(_)) => return Ordering::Less,
+            (Some(_), Some('~')) => return Ordering::Greater,
+            (Some(a), Some(b)) => (a.is_ascii_digit(), b.is_ascii_digit()),
+        };
+        if a_digits != b_digits {
+            return a_digits.cmp(&b_digits);
+        }
+
+        let (a_segment, a_rest) = version_segment(a, a_digits);
+        let (b_segment, b_rest) = version_segment(b, b_digits);
+        let ordering = if a_digits {
+            let a_segment = a_segment.trim_start_matches('0');
+            let b_segment = b_segment.trim_start_matches('0');
+            a_segment
+                .len()
+                .cmp(&b_segment.len())
+                .then_with(|| a_segment.cmp(b_segment))
+        } else {
+            a_segment.cmp(b_segment)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        (a, b) = (a_rest, b_rest);
+    }
+}
+
+impl BootEntry {
+    /// The value of the `version` key, if this entry has one
+    pub fn version(&self) -> Option<&str> {
+        self.keys.iter().find_map(|key| match key {
+            EntryKey::Version(version) => Some(version.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The value of the `architecture` key, if this entry has one, as written. Architectures are
+    /// compared case-insensitively.
+    pub fn architecture(&self) -> Option<&str> {
+        self.keys.iter().find_map(|key| match key {
+            EntryKey::Architecture(architecture) => Some(architecture.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Order entries newest-first by their `version` key, for use with [slice::sort_by]. Entries
+    /// without a version sort after all entries that have one.
+    pub fn newest_first(a: &BootEntry, b: &BootEntry) -> Ordering {
+        match (a.version(), b.version()) {
+            (Some(a), Some(b)) => compare_versions(b, a),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+
+    /// Returns a copy of this entry with the kernel parameters in each `options` key sorted, so
+    /// that entries can be compared or diffed. Order-sensitive parameters (such as `console=`)
+    /// keep their relative order.
+    pub fn normalized(&self) -> BootEntry {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| match key {
+                EntryKey::Options(options) => EntryKey::Options(normalize_options(options)),
+                key => key.clone(),
+            })
+            .collect();
+        BootEntry { keys }
+    }
+
+    /// Compare two entries, ignoring the order of kernel parameters in `options` keys (except for
+    /// order-sensitive parameters, such as `console=`). Unlike [PartialEq], `quiet ro` and `ro
+    /// quiet` are equal.
+    pub fn semantically_eq(&self, other: &BootEntry) -> bool {
+        self.normalized() == other.normalized()
+    }
+
+    /// Overlay `other` onto this entry. Keys that take a single value (e.g. `title`, `linux`,
+    /// `devicetree`) are replaced by their value in `other`, and the kernel parameters of `options`
+    /// keys in `other` are appended to those of this entry.
+    pub fn merge(&mut self, other: &BootEntry) {
+        for key in &other.keys {
+            match key {
+                EntryKey::Options(options) => {
+                    match self
+                        .keys
+                        .iter_mut()
+                        .rev()
+                        .find_map(|current| match current {
+                            EntryKey::Options(current) => Some(current),
+                            _ => None,
+                        }) {
+                        Some(current) => current.extend(options.iter().cloned()),
+                        None => self.keys.push(key.clone()),
+                    }
+                }
+                key => match self
+                    .keys
+                    .iter_mut()
+                    .find(|current| mem::discriminant(*current) == mem::discriminant(key))
+                {
+                    Some(current) => *current = key.clone(),
+                    None => self.keys.push(key.clone()),
+                },
+            }
+        }
+    }
+}
+
 impl fmt::Display for BootEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for key in &self.keys {
             key.fmt(f)?;
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         Ok(())
     }
@@ -86,6 +311,44 @@ impl FromStr for BootEntry {
     }
 }
 
+/// The reason a directory of boot entries could not be loaded
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    #[error("failed to read {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("invalid boot entry in {0}")]
+    InvalidEntry(PathBuf, #[source] crate::Error),
+}
+
+/// Read every `*.conf` file in a directory (such as `/boot/loader/entries`), in order of their
+/// names. Other files are skipped.
+pub fn load_entries_dir(directory: &Path) -> Result<Vec<BootEntry>, LoadError> {
+    let io_error = |error| LoadError::Io(directory.into(), error);
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(directory).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "conf")
+            && path.is_file()
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let contents =
+                std::fs::read_to_string(&path).map_err(|e| LoadError::Io(path.clone(), e))?;
+            contents
+                .parse()
+                .map_err(|e| LoadError::InvalidEntry(path.clone(), e))
+        })
+        .collect()
+}
+
 /// Modules and routines meant to aid deserializing UAPI bootloader entries using serde field
 /// attributes.
 #[cfg(feature = "serde")]
@@ -110,6 +373,234 @@ pub mod serde {
 mod test {
     use super::*;
 
+    fn options(options: &[&str]) -> EntryKey {
+        EntryKey::Options(options.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn load_entries_dir() {
+        let directory = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &str| {
+            std::fs::write(directory.path().join(name), contents).unwrap()
+        };
+        write(
+            "fedora-6.2.conf",
+            "title Fedora\nversion 6.2\nlinux /vmlinuz-6.2\n",
+        );
+        write(
+            "fedora-6.1.conf",
+            "title Fedora\nversion 6.1\nlinux /vmlinuz-6.1\n",
+        );
+        write("README", "not an entry\n");
+        std::fs::create_dir(directory.path().join("old.conf")).unwrap();
+
+        let entries = super::load_entries_dir(directory.path()).unwrap();
+        assert_eq!(
+            entries.iter().map(BootEntry::version).collect::<Vec<_>>(),
+            vec![Some("6.1"), Some("6.2")]
+        );
+
+        write("broken.conf", "title Broken\nnot-a-key value\n");
+        match super::load_entries_dir(directory.path()) {
+            Err(LoadError::InvalidEntry(path, _)) => {
+                assert_eq!(path, directory.path().join("broken.conf"))
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        assert!(matches!(
+            super::load_entries_dir(&directory.path().join("missing")),
+            Err(LoadError::Io(_, _))
+        ));
+    }
+
+    #[test]
+    fn devicetree_overlay() {
+        use crate::BootFile;
+
+        let key: EntryKey = "devicetree-overlay /a.dtbo /b.dtbo".parse().unwrap();
+        assert_eq!(key.to_string(), "devicetree-overlay /a.dtbo /b.dtbo");
+        assert_eq!(
+            key.boot_files(),
+            vec![Path::new("/a.dtbo"), Path::new("/b.dtbo")]
+        );
+    }
+
+    #[test]
+    fn quoted_options() {
+        let entry = options(&["init=/usr/lib/systemd systemd", "motd=\"hi\"", "quiet"]);
+        let line = entry.to_string();
+        assert_eq!(
+            line,
+            r#"options init="/usr/lib/systemd systemd" motd="\"hi\"" quiet"#
+        );
+        assert_eq!(line.parse::<EntryKey>().unwrap(), entry);
+    }
+
+    #[test]
+    fn version_and_machine_id() {
+        let entry: BootEntry = "title Fedora\nversion 6.1.0\nmachine-id 6a9857a3\nlinux /Image\n"
+            .parse()
+            .unwrap();
+        assert_eq!(entry.version(), Some("6.1.0"));
+        assert_eq!(
+            entry.to_string(),
+            "title Fedora\nversion 6.1.0\nmachine-id 6a9857a3\nlinux /Image\n"
+        );
+    }
+
+    #[test]
+    fn architecture() {
+        let entry: BootEntry = "title Fedora\narchitecture aa64\nlinux /Image\n"
+            .parse()
+            .unwrap();
+        assert_eq!(entry.architecture(), Some("aa64"));
+        assert_eq!(
+            entry.to_string(),
+            "title Fedora\narchitecture aa64\nlinux /Image\n"
+        );
+        let label: crate::syslinux::Label = entry.try_into().unwrap();
+        assert!(label.directives.is_empty());
+
+        let entry: BootEntry = "title Fedora\narchitecture X64\nlinux /bzImage\n"
+            .parse()
+            .unwrap();
+        assert_eq!(entry.architecture(), Some("X64"));
+        assert_eq!(
+            entry.to_string(),
+            "title Fedora\narchitecture X64\nlinux /bzImage\n"
+        );
+    }
+
+    #[test]
+    fn sort_newest_first() {
+        let entry = |version: Option<&str>| BootEntry {
+            keys: version
+                .map(|version| EntryKey::Version(version.to_string()))
+                .into_iter()
+                .collect(),
+        };
+        let mut entries = [
+            entry(Some("6.9.0")),
+            entry(None),
+            entry(Some("6.10.1")),
+            entry(Some("6.1.0")),
+            entry(Some("6.10.0")),
+            entry(Some("6.10.0-rc1")),
+            entry(Some("6.10.0~rc2")),
+        ];
+        entries.sort_by(BootEntry::newest_first);
+        assert_eq!(
+            entries.iter().map(BootEntry::version).collect::<Vec<_>>(),
+            vec![
+                Some("6.10.1"),
+                Some("6.10.0"),
+                Some("6.10.0-rc1"),
+                Some("6.10.0~rc2"),
+                Some("6.9.0"),
+                Some("6.1.0"),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn normalized_sorts_options() {
+        let entry = BootEntry {
+            keys: vec![
+                EntryKey::Linux("/Image".into()),
+                options(&["rw", "quiet", "root=/dev/sda1"]),
+            ],
+        };
+        assert_eq!(
+            entry.normalized(),
+            BootEntry {
+                keys: vec![
+                    EntryKey::Linux("/Image".into()),
+                    options(&["quiet", "root=/dev/sda1", "rw"]),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn normalized_preserves_console_order() {
+        let entry = BootEntry {
+            keys: vec![options(&[
+                "quiet",
+                "console=ttyS0,115200",
+                "ro",
+                "console=tty0",
+                "--",
+                "single",
+                "emergency",
+            ])],
+        };
+        assert_eq!(
+            entry.normalized(),
+            BootEntry {
+                keys: vec![options(&[
+                    "console=ttyS0,115200",
+                    "console=tty0",
+                    "quiet",
+                    "ro",
+                    "--",
+                    "single",
+                    "emergency",
+                ])],
+            }
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let mut entry: BootEntry = "title Fedora\nlinux /Image\noptions quiet ro\n"
+            .parse()
+            .unwrap();
+        let machine: BootEntry =
+            "title Fedora (rack 2)\noptions console=ttyS0\ndevicetree /rack2.dtb\n"
+                .parse()
+                .unwrap();
+        entry.merge(&machine);
+        assert_eq!(
+            entry.to_string(),
+            "title Fedora (rack 2)\nlinux /Image\noptions quiet ro console=ttyS0\ndevicetree /rack2.dtb\n"
+        );
+
+        // Merging into an entry without options adds them
+        let mut entry: BootEntry = "title Fedora\nlinux /Image\n".parse().unwrap();
+        entry.merge(&"linux /vmlinuz\noptions quiet\n".parse().unwrap());
+        assert_eq!(
+            entry.to_string(),
+            "title Fedora\nlinux /vmlinuz\noptions quiet\n"
+        );
+    }
+
+    #[test]
+    fn semantically_eq() {
+        let a = BootEntry {
+            keys: vec![
+                EntryKey::Linux("/Image".into()),
+                options(&["console=ttyS0", "quiet", "ro", "console=tty0"]),
+            ],
+        };
+        let b = BootEntry {
+            keys: vec![
+                EntryKey::Linux("/Image".into()),
+                options(&["ro", "console=ttyS0", "console=tty0", "quiet"]),
+            ],
+        };
+        let c = BootEntry {
+            keys: vec![
+                EntryKey::Linux("/Image".into()),
+                options(&["console=tty0", "quiet", "ro", "console=ttyS0"]),
+            ],
+        };
+        assert_ne!(a, b);
+        assert!(a.semantically_eq(&b));
+        assert!(!a.semantically_eq(&c));
+    }
+
     #[test]
     fn boot_entry_with_trailing_junk() {
         let result = BootEntry::from_str("linux /Image\ndevisetree");
@@ -1,24 +1,1419 @@
-use std::net::SocketAddr;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
-use boot_loader_entries::uapi;
-use serde::Deserialize;
+use boot_loader_entries::{syslinux, uapi};
+use regex::{Captures, Regex};
+use serde::{de, Deserialize};
 
-use crate::instant_netboot::NfsConfiguration;
+use crate::{
+    instant_netboot::{self, ConfigPrefix, NfsShares},
+    proxy_dhcp::ProxyDhcp,
+    resolver::{Ipv4Network, MapResolver},
+};
 
 fn default_socket() -> SocketAddr {
     "0.0.0.0:6969".parse().unwrap()
 }
 
+fn default_generated_tsize() -> bool {
+    true
+}
+
+fn default_proxy_dhcp_socket() -> SocketAddr {
+    "0.0.0.0:67".parse().unwrap()
+}
+
+fn default_config_prefixes() -> Vec<ConfigPrefix> {
+    vec![ConfigPrefix::pxelinux()]
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed to read {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("invalid boot entry in {0}")]
+    InvalidBootEntry(PathBuf, #[source] boot_loader_entries::Error),
+    #[error("invalid YAML configuration")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid TOML configuration")]
+    Toml(#[from] toml::de::Error),
+    #[error("environment variable {0} is not set")]
+    UndefinedVariable(String),
+}
+
+/// Substitute `${VAR}` with the value of the variable `VAR` from `lookup`. `$${VAR}` is left as
+/// the literal `${VAR}`. Macros that aren't valid variable names, such as `${next-server}`, are
+/// left alone.
+fn expand_variables(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    static VARIABLE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"\$(\$?)\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap());
+
+    let mut undefined = None;
+    let expanded = VARIABLE.replace_all(value, |captures: &Captures| {
+        let name = &captures[2];
+        if !captures[1].is_empty() {
+            return format!("${{{}}}", name);
+        }
+        lookup(name).unwrap_or_else(|| {
+            undefined.get_or_insert_with(|| name.to_string());
+            String::new()
+        })
+    });
+    match undefined {
+        Some(name) => Err(Error::UndefinedVariable(name)),
+        None => Ok(expanded.into_owned()),
+    }
+}
+
+/// A problem with the contents of a configuration
+#[derive(thiserror::Error, Debug)]
+pub enum ValidationError {
+    #[error("boot entry file {0} has not been loaded")]
+    UnresolvedBootEntry(PathBuf),
+    #[error("invalid PXE boot entry")]
+    InvalidBootEntry(#[from] syslinux::ConfigurationConversionError),
+    #[error("boot file {0} does not exist")]
+    MissingBootFile(PathBuf),
+    #[error("nfs.share is empty")]
+    EmptyNfsShare,
+    #[error("nfs.share {0} is not an absolute path")]
+    RelativeNfsShare(PathBuf),
+    #[error("nfs.share {0} must not contain \"..\" with NFSv4")]
+    InvalidNfsV4Share(PathBuf),
+    #[error("default entry {0} is not the title of any boot entry")]
+    UnknownDefault(String),
+    #[error("NFS share label {0} is not the title of any boot entry")]
+    UnknownNfsLabel(String),
+    #[error("entry {1} for {0} is not the title of any boot entry")]
+    UnknownIpLabel(Ipv4Network, String),
+    #[error("no boot entry for architecture {0}, which clients request from a config prefix")]
+    NoEntriesForArchitecture(String),
+    #[error("proxy-dhcp needs the IPv4 address of the TFTP server in tftp.next-server, not {0}")]
+    ProxyDhcpServerAddress(IpAddr),
+    #[error("proxy-dhcp boot file {0} is longer than 127 bytes")]
+    ProxyDhcpBootFileTooLong(String),
+    #[error("${{next-server}} needs the address of the TFTP server in tftp.next-server, not {0}")]
+    NextServerUnspecified(IpAddr),
+}
+
+/// The format of a configuration file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// Determine the format of a configuration file from its extension. Files with an
+    /// unrecognized extension, or no extension at all, are assumed to be YAML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Format::Toml,
+            _ => Format::Yaml,
+        }
+    }
+}
+
+/// A boot loader entry, either inline in the configuration or in a separate file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BootEntrySource {
+    /// The contents of the boot loader entry
+    Inline(uapi::BootEntry),
+    /// A path to a boot loader entry file. Relative paths are resolved against the directory
+    /// containing the configuration file.
+    File(PathBuf),
+}
+
+impl BootEntrySource {
+    /// Read the boot loader entry, resolving relative file references against `base`.
+    pub fn load(self, base: &Path) -> Result<uapi::BootEntry, Error> {
+        match self {
+            BootEntrySource::Inline(entry) => Ok(entry),
+            BootEntrySource::File(path) => {
+                let path = base.join(path);
+                let contents =
+                    std::fs::read_to_string(&path).map_err(|e| Error::Io(path.clone(), e))?;
+                contents
+                    .parse()
+                    .map_err(|e| Error::InvalidBootEntry(path, e))
+            }
+        }
+    }
+}
+
+struct BootEntrySourceVisitor;
+
+impl<'de> de::Visitor<'de> for BootEntrySourceVisitor {
+    type Value = BootEntrySource;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a boot loader entry or a map with a \"file\" key")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value
+            .parse()
+            .map(BootEntrySource::Inline)
+            .map_err(de::Error::custom)
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct FileReference {
+            file: PathBuf,
+        }
+
+        let reference: FileReference =
+            Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok(BootEntrySource::File(reference.file))
+    }
+}
+
+impl<'de> Deserialize<'de> for BootEntrySource {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(BootEntrySourceVisitor)
+    }
+}
+
+/// One boot loader entry, or a list of them
+struct BootEntrySources(Vec<BootEntrySource>);
+
+impl<'de> Deserialize<'de> for BootEntrySources {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct BootEntrySourcesVisitor;
+
+        impl<'de> de::Visitor<'de> for BootEntrySourcesVisitor {
+            type Value = BootEntrySources;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("one boot loader entry, or a list of them")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(BootEntrySources(vec![
+                    BootEntrySourceVisitor.visit_str(value)?
+                ]))
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::MapAccess<'de>,
+            {
+                Ok(BootEntrySources(vec![
+                    BootEntrySourceVisitor.visit_map(map)?
+                ]))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let mut entries = Vec::new();
+                while let Some(entry) = seq.next_element()? {
+                    entries.push(entry);
+                }
+                if entries.is_empty() {
+                    return Err(de::Error::invalid_length(0, &self));
+                }
+                Ok(BootEntrySources(entries))
+            }
+        }
+
+        deserializer.deserialize_any(BootEntrySourcesVisitor)
+    }
+}
+
 #[derive(Deserialize)]
-pub struct NetbootConfiguration {
+#[serde(rename_all = "kebab-case")]
+struct RawNetbootConfiguration {
     #[serde(default = "default_socket")]
+    socket: SocketAddr,
+    next_server: Option<IpAddr>,
+    root: Option<PathBuf>,
+    pxe: Option<BootEntrySources>,
+    pxe_file: Option<PathBuf>,
+    default: Option<String>,
+    menu_title: Option<String>,
+    #[serde(default = "default_generated_tsize")]
+    generated_tsize: bool,
+    #[serde(default)]
+    decompress_gzip: bool,
+    append_directory: Option<PathBuf>,
+    #[serde(default)]
+    ip_labels: BTreeMap<Ipv4Network, String>,
+    block_size_limit: Option<u16>,
+    rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    allow_write: bool,
+    #[serde(default = "default_config_prefixes")]
+    config_prefixes: Vec<ConfigPrefix>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawNetbootConfiguration")]
+pub struct NetbootConfiguration {
     pub socket: SocketAddr,
-    #[serde(deserialize_with = "uapi::serde::from_str::deserialize")]
-    pub pxe: uapi::BootEntry,
+    /// The address substituted for `${next-server}` in the boot entry. Defaults to the IP address
+    /// of `socket`.
+    pub next_server: Option<IpAddr>,
+    /// A directory from which any other requested files are served, such as boot loader binaries.
+    pub root: Option<PathBuf>,
+    /// The boot loader entries. Given either as `pxe` (one entry or a list of them, each inline
+    /// or `{ file: path }`) or `pxe-file`. Entries with a `version` key are reordered: the menu
+    /// lists them newest first (a release before its `-rc` or `~rc` pre-releases), followed by
+    /// the entries without one in the order given here.
+    pub pxe: Vec<BootEntrySource>,
+    /// The title of the entry booted by default. Defaults to the first entry in the menu, which
+    /// is the newest one if entries have versions.
+    pub default: Option<String>,
+    /// The title shown at the top of the PXELINUX menu
+    pub menu_title: Option<String>,
+    /// Whether to give clients the size (tsize) of generated PXE configurations. Defaults to true.
+    pub generated_tsize: bool,
+    /// Whether to serve `<file>.gz`, decompressed, to clients requesting a `<file>` that doesn't
+    /// exist. Clients aren't given the size of these files. Defaults to false.
+    pub decompress_gzip: bool,
+    /// A directory of `<client>.append` files, holding extra kernel arguments for each client.
+    pub append_directory: Option<PathBuf>,
+    /// The title of the entry booted by clients that request their configuration by an IP
+    /// address (e.g. `192.168.1.5`) or an address in a CIDR range (e.g. `192.168.1.0/24`).
+    pub ip_labels: BTreeMap<Ipv4Network, String>,
+    /// The largest block size (RFC 2348 `blksize`) negotiated with clients. Without a limit,
+    /// clients get the block size they ask for. The `windowsize` option (RFC 7440) isn't
+    /// supported, so clients wait for every block to be acknowledged.
+    pub block_size_limit: Option<u16>,
+    /// Limits the rate of requests from each client address, so that a client re-requesting the
+    /// same file in a loop can't swamp the server. Unlimited by default.
+    pub rate_limit: Option<RateLimit>,
+    /// Whether clients may write files. There is no writable backend yet, so writes are refused
+    /// either way: with "access violation" when false (the default), which clients that probe
+    /// with a write treat as final, and "illegal operation" when true. Defaults to false.
+    pub allow_write: bool,
+    /// The directories clients request their configuration from, and the format served from
+    /// each. Defaults to PXELINUX configurations in `pxelinux.cfg`.
+    pub config_prefixes: Vec<ConfigPrefix>,
 }
 
-#[derive(Deserialize)]
+impl TryFrom<RawNetbootConfiguration> for NetbootConfiguration {
+    type Error = &'static str;
+
+    fn try_from(value: RawNetbootConfiguration) -> Result<Self, Self::Error> {
+        let pxe = match (value.pxe, value.pxe_file) {
+            (Some(pxe), None) => pxe.0,
+            (None, Some(path)) => vec![BootEntrySource::File(path)],
+            (Some(_), Some(_)) => return Err("only one of `pxe` and `pxe-file` may be given"),
+            (None, None) => return Err("one of `pxe` or `pxe-file` is required"),
+        };
+        if let Some(limit) = value.rate_limit {
+            if limit.burst < 1 {
+                return Err("`rate-limit.burst` must be at least 1");
+            }
+            if limit.per_second.is_nan() || limit.per_second <= 0.0 {
+                return Err("`rate-limit.per-second` must be positive");
+            }
+        }
+        Ok(NetbootConfiguration {
+            socket: value.socket,
+            next_server: value.next_server,
+            root: value.root,
+            pxe,
+            default: value.default,
+            menu_title: value.menu_title,
+            generated_tsize: value.generated_tsize,
+            decompress_gzip: value.decompress_gzip,
+            append_directory: value.append_directory,
+            ip_labels: value.ip_labels,
+            block_size_limit: value.block_size_limit,
+            rate_limit: value.rate_limit,
+            allow_write: value.allow_write,
+            config_prefixes: value.config_prefixes,
+        })
+    }
+}
+
+/// A token bucket: a client may make `burst` requests at once, and `per-second` requests a second
+/// after that. Requests over the limit are delayed until the client has a token.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Serves boot files over HTTP, for UEFI HTTP Boot
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HttpConfiguration {
+    pub socket: SocketAddr,
+}
+
+/// Answers PXE clients' DHCP discovery with the boot file, for networks where the DHCP server can't
+/// be configured. Addresses are still assigned by the DHCP server.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProxyDhcpConfiguration {
+    #[serde(default = "default_proxy_dhcp_socket")]
+    pub socket: SocketAddr,
+    /// The boot file for BIOS clients (e.g. `pxelinux.0`)
+    pub boot_file: String,
+    /// The boot file for UEFI clients (e.g. `grubx64.efi`). Defaults to `boot-file`.
+    pub uefi_boot_file: Option<String>,
+}
+
+/// Answers health checks, for orchestrators
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthConfiguration {
+    pub socket: SocketAddr,
+}
+
+/// What to do when a boot entry references a boot file that doesn't exist
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingBootFilePolicy {
+    /// Refuse to start
+    #[default]
+    Fail,
+    /// Log a warning and serve the configuration anyway
+    Warn,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct Configuration {
     pub tftp: NetbootConfiguration,
-    pub nfs: Option<NfsConfiguration>,
+    pub http: Option<HttpConfiguration>,
+    /// Answers HTTP requests with 200 once the configuration is valid and the servers are
+    /// listening
+    pub health: Option<HealthConfiguration>,
+    #[serde(rename = "proxy-dhcp")]
+    pub proxy_dhcp: Option<ProxyDhcpConfiguration>,
+    /// One NFS share mounted by every entry, or a list of shares, each mounted by the entry named
+    /// by its `label` key. A share in the list without a `label` is mounted by the other entries.
+    pub nfs: Option<NfsShares>,
+    /// Whether to substitute `${VAR}` in kernel options and the NFS share with the value of the
+    /// environment variable `VAR` when the configuration is loaded. Defaults to false.
+    #[serde(default, rename = "expand-environment")]
+    pub expand_environment: bool,
+    /// Whether a boot file that doesn't exist is an error, or only a warning. Defaults to an
+    /// error.
+    #[serde(default, rename = "on-missing-boot-file")]
+    pub on_missing_boot_file: MissingBootFilePolicy,
+}
+
+impl Configuration {
+    /// Parse a configuration in the given format.
+    pub fn parse(contents: &str, format: Format) -> Result<Self, Error> {
+        Ok(match format {
+            Format::Yaml => serde_yaml::from_str(contents)?,
+            Format::Toml => toml::from_str(contents)?,
+        })
+    }
+
+    /// Read a configuration file. The format is determined from the extension of the path. Boot
+    /// entries in separate files are read, relative to the directory containing the configuration
+    /// file.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e))?;
+        let mut configuration = Self::parse(&contents, Format::from_path(path))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        configuration.tftp.pxe = std::mem::take(&mut configuration.tftp.pxe)
+            .into_iter()
+            .map(|entry| entry.load(base).map(BootEntrySource::Inline))
+            .collect::<Result<_, _>>()?;
+        if configuration.expand_environment {
+            configuration.expand_variables(|name| std::env::var(name).ok())?;
+        }
+        Ok(configuration)
+    }
+
+    /// Substitute variables in the kernel options of each inline boot entry, and the NFS share.
+    fn expand_variables(
+        &mut self,
+        lookup: impl Fn(&str) -> Option<String> + Copy,
+    ) -> Result<(), Error> {
+        for entry in &mut self.tftp.pxe {
+            let BootEntrySource::Inline(entry) = entry else {
+                continue;
+            };
+            for key in &mut entry.keys {
+                if let uapi::EntryKey::Options(options) = key {
+                    for option in options {
+                        *option = expand_variables(option, lookup)?;
+                    }
+                }
+            }
+        }
+        for nfs in self.nfs.iter_mut().flat_map(NfsShares::iter_mut) {
+            nfs.share = expand_variables(&nfs.share.to_string_lossy(), lookup)?.into();
+        }
+        Ok(())
+    }
+
+    /// The boot loader entries, in menu order. Fails if an entry is in a file that has not been
+    /// read.
+    pub fn boot_entries(&self) -> Result<Vec<&uapi::BootEntry>, ValidationError> {
+        let mut entries = self
+            .tftp
+            .pxe
+            .iter()
+            .map(|entry| match entry {
+                BootEntrySource::Inline(entry) => Ok(entry),
+                BootEntrySource::File(path) => {
+                    Err(ValidationError::UnresolvedBootEntry(path.clone()))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // The sort is stable, so entries without versions stay in the order they were given
+        entries.sort_by(|a, b| uapi::BootEntry::newest_first(a, b));
+        Ok(entries)
+    }
+
+    /// The configuration served to PXE clients, with a label for each boot loader entry
+    pub fn syslinux_configuration(&self) -> Result<syslinux::Configuration, ValidationError> {
+        let mut labels = self
+            .boot_entries()?
+            .into_iter()
+            .map(|entry| entry.clone().try_into())
+            .collect::<Result<Vec<syslinux::Label>, _>>()?;
+        syslinux::make_names_unique(&mut labels);
+        let default = match &self.tftp.default {
+            Some(default) => labels
+                .iter()
+                .find(|label| label.title() == *default)
+                .ok_or_else(|| ValidationError::UnknownDefault(default.clone()))?,
+            // INVARIANT: There is at least one boot loader entry
+            None => &labels[0],
+        };
+        Ok(syslinux::Configuration {
+            default: Some(default.name.clone()),
+            menu_title: self.tftp.menu_title.clone(),
+            labels,
+        })
+    }
+
+    /// Selects the configuration for each client. Clients matching `ip-labels` are served only
+    /// the entry given for them. Clients requesting from a config prefix with an architecture are
+    /// served the entries for that architecture, and entries without an `architecture` key. Any
+    /// other client is served every entry.
+    pub fn resolver(&self) -> Result<MapResolver, ValidationError> {
+        let configuration = self.syslinux_configuration()?;
+        let mut networks = Vec::new();
+        for (network, name) in &self.tftp.ip_labels {
+            let label = configuration
+                .labels
+                .iter()
+                .find(|label| label.title() == *name)
+                .ok_or_else(|| ValidationError::UnknownIpLabel(*network, name.clone()))?;
+            networks.push((*network, label.clone()));
+        }
+
+        let menu_title = configuration.menu_title.clone();
+        let mut resolver = MapResolver::new(configuration);
+        for (network, label) in networks {
+            let configuration = syslinux::Configuration {
+                default: Some(label.name.clone()),
+                menu_title: menu_title.clone(),
+                labels: vec![label],
+            };
+            resolver.insert_network(network, configuration);
+        }
+        for architecture in self
+            .tftp
+            .config_prefixes
+            .iter()
+            .flat_map(|prefix| &prefix.architecture)
+        {
+            resolver.insert_architecture(
+                architecture.clone(),
+                self.architecture_configuration(architecture)?,
+            );
+        }
+        Ok(resolver)
+    }
+
+    /// The configuration served to clients of this architecture. The default entry is the one
+    /// named by `default`, if it is for this architecture, or the first entry otherwise.
+    fn architecture_configuration(
+        &self,
+        architecture: &str,
+    ) -> Result<syslinux::Configuration, ValidationError> {
+        let configuration = self.syslinux_configuration()?;
+        let labels = self
+            .boot_entries()?
+            .into_iter()
+            .zip(configuration.labels)
+            .filter(|(entry, _)| {
+                entry
+                    .architecture()
+                    .is_none_or(|entry| entry.eq_ignore_ascii_case(architecture))
+            })
+            .map(|(_, label)| label)
+            .collect::<Vec<_>>();
+        let default = match &configuration.default {
+            Some(default) if labels.iter().any(|label| label.name == *default) => default.clone(),
+            _ => labels
+                .first()
+                .ok_or_else(|| ValidationError::NoEntriesForArchitecture(architecture.to_string()))?
+                .name
+                .clone(),
+        };
+        Ok(syslinux::Configuration {
+            default: Some(default),
+            menu_title: configuration.menu_title,
+            labels,
+        })
+    }
+
+    /// The address substituted for `${next-server}`: `tftp.next-server`, or the address the TFTP
+    /// server is bound to.
+    pub fn next_server(&self) -> IpAddr {
+        self.tftp.next_server.unwrap_or(self.tftp.socket.ip())
+    }
+
+    /// What the ProxyDHCP server tells clients, if it is enabled. Clients are sent to the address
+    /// substituted for `${next-server}`, which must be an IPv4 address.
+    pub fn proxy_dhcp(&self) -> Result<Option<ProxyDhcp>, ValidationError> {
+        let Some(proxy_dhcp) = &self.proxy_dhcp else {
+            return Ok(None);
+        };
+        let server = match self.next_server() {
+            IpAddr::V4(address) if !address.is_unspecified() => address,
+            address => return Err(ValidationError::ProxyDhcpServerAddress(address)),
+        };
+        for file in [
+            Some(&proxy_dhcp.boot_file),
+            proxy_dhcp.uefi_boot_file.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            // The file field of a DHCP packet is 128 bytes, including the terminator
+            if file.len() > 127 {
+                return Err(ValidationError::ProxyDhcpBootFileTooLong(file.clone()));
+            }
+        }
+        Ok(Some(ProxyDhcp {
+            server,
+            boot_file: proxy_dhcp.boot_file.clone(),
+            uefi_boot_file: proxy_dhcp.uefi_boot_file.clone(),
+        }))
+    }
+
+    /// The files and directories referenced by the boot entries that don't exist.
+    pub fn missing_boot_files(&self) -> Result<Vec<PathBuf>, ValidationError> {
+        let next_server = self.next_server();
+        let mut missing = Vec::new();
+        for label in self.syslinux_configuration()?.labels {
+            let label = instant_netboot::expand_label_macros(label, &next_server);
+            let boot_files = instant_netboot::listed_files(&label)
+                .chain(instant_netboot::listed_directories(&label));
+            for file in boot_files {
+                let compressed = || {
+                    let mut compressed = file.as_os_str().to_owned();
+                    compressed.push(".gz");
+                    Path::new(&compressed).exists()
+                };
+                let exists = file.exists() || (self.tftp.decompress_gzip && compressed());
+                if !exists && !missing.iter().any(|path| path == file) {
+                    missing.push(file.to_path_buf());
+                }
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Check that the boot entries can be served, that the boot files they reference exist, and
+    /// that the NFS configuration is sensible.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.resolver()?;
+        self.proxy_dhcp()?;
+        // A server bound to every interface has no single address for clients to reach it by
+        let next_server = self.next_server();
+        if next_server.is_unspecified()
+            && self
+                .syslinux_configuration()?
+                .labels
+                .into_iter()
+                .any(|label| {
+                    instant_netboot::expand_label_macros(label.clone(), &next_server) != label
+                })
+        {
+            return Err(ValidationError::NextServerUnspecified(next_server));
+        }
+        for file in self.missing_boot_files()? {
+            match self.on_missing_boot_file {
+                MissingBootFilePolicy::Fail => return Err(ValidationError::MissingBootFile(file)),
+                MissingBootFilePolicy::Warn => {
+                    tracing::warn!("Boot file {} does not exist", file.display())
+                }
+            }
+        }
+
+        let labels = self.syslinux_configuration()?.labels;
+        let shares = self.nfs.as_ref();
+        if let Some(name) = shares
+            .into_iter()
+            .flat_map(|shares| shares.labels.keys())
+            .find(|name| !labels.iter().any(|label| label.title() == **name))
+        {
+            return Err(ValidationError::UnknownNfsLabel(name.clone()));
+        }
+        for nfs in shares.into_iter().flat_map(NfsShares::iter) {
+            if nfs.share.as_os_str().is_empty() {
+                return Err(ValidationError::EmptyNfsShare);
+            }
+            if !nfs.share.is_absolute() {
+                return Err(ValidationError::RelativeNfsShare(nfs.share.clone()));
+            }
+            // NFSv4 paths are resolved by the server within its pseudo-filesystem, which doesn't
+            // allow leaving the root.
+            if matches!(nfs.version, instant_netboot::NfsVersion::NFSv4 { .. })
+                && nfs
+                    .share
+                    .components()
+                    .any(|component| component == std::path::Component::ParentDir)
+            {
+                return Err(ValidationError::InvalidNfsV4Share(nfs.share.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BOOT_ENTRY: &str = "title Fedora 19 (Rawhide)\nlinux /Image\ndevicetree /boot.dtb\n";
+
+    fn parse(yaml: &str) -> Configuration {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn inline_and_file_entries_are_equal() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("entry.conf"), BOOT_ENTRY).unwrap();
+
+        let inline = parse(
+            "tftp:\n  pxe: |\n    title Fedora 19 (Rawhide)\n    linux /Image\n    devicetree /boot.dtb\n",
+        );
+        let file = parse("tftp:\n  pxe:\n    file: entry.conf\n");
+        assert_eq!(
+            file.tftp.pxe,
+            vec![BootEntrySource::File("entry.conf".into())]
+        );
+
+        let inline = inline.tftp.pxe[0].clone().load(directory.path()).unwrap();
+        let file = file.tftp.pxe[0].clone().load(directory.path()).unwrap();
+        assert_eq!(inline, BOOT_ENTRY.parse().unwrap());
+        assert_eq!(inline, file);
+    }
+
+    #[test]
+    fn pxe_file() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("entry.conf"), BOOT_ENTRY).unwrap();
+
+        let configuration = parse("tftp:\n  pxe-file: entry.conf\n");
+        assert_eq!(
+            configuration.tftp.pxe,
+            vec![BootEntrySource::File("entry.conf".into())]
+        );
+        assert_eq!(
+            configuration.tftp.pxe[0]
+                .clone()
+                .load(directory.path())
+                .unwrap(),
+            BOOT_ENTRY.parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn pxe_and_pxe_file_are_exclusive() {
+        let both = "tftp:\n  pxe: linux /Image\n  pxe-file: entry.conf\n";
+        assert!(serde_yaml::from_str::<Configuration>(both).is_err());
+        let neither = "tftp:\n  socket: 0.0.0.0:69\n";
+        assert!(serde_yaml::from_str::<Configuration>(neither).is_err());
+    }
+
+    #[test]
+    fn generated_tsize() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert!(configuration.tftp.generated_tsize);
+        let configuration = parse("tftp:\n  pxe: linux /Image\n  generated-tsize: false\n");
+        assert!(!configuration.tftp.generated_tsize);
+    }
+
+    #[test]
+    fn decompress_gzip() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        std::fs::write(directory.path().join("Image.gz"), "compressed").unwrap();
+        let yaml = format!(
+            "tftp:\n  pxe: |\n    title Test\n    linux {}\n",
+            kernel.display()
+        );
+        let configuration = parse(&yaml);
+        assert!(!configuration.tftp.decompress_gzip);
+        assert_eq!(configuration.missing_boot_files().unwrap(), vec![kernel]);
+
+        let configuration = parse(&format!("{}  decompress-gzip: true\n", yaml));
+        assert!(configuration.tftp.decompress_gzip);
+        assert_eq!(
+            configuration.missing_boot_files().unwrap(),
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[test]
+    fn rate_limit() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert_eq!(configuration.tftp.rate_limit, None);
+        let configuration = parse(
+            "tftp:\n  pxe: linux /Image\n  rate-limit:\n    burst: 10\n    per-second: 0.5\n",
+        );
+        assert_eq!(
+            configuration.tftp.rate_limit,
+            Some(RateLimit {
+                burst: 10,
+                per_second: 0.5
+            })
+        );
+        let invalid =
+            "tftp:\n  pxe: linux /Image\n  rate-limit:\n    burst: 10\n    per-second: 0\n";
+        assert!(serde_yaml::from_str::<Configuration>(invalid).is_err());
+        let invalid =
+            "tftp:\n  pxe: linux /Image\n  rate-limit:\n    burst: 0\n    per-second: 0.5\n";
+        assert!(serde_yaml::from_str::<Configuration>(invalid).is_err());
+    }
+
+    #[test]
+    fn block_size_limit() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert_eq!(configuration.tftp.block_size_limit, None);
+        let configuration = parse("tftp:\n  pxe: linux /Image\n  block-size-limit: 1468\n");
+        assert_eq!(configuration.tftp.block_size_limit, Some(1468));
+    }
+
+    #[test]
+    fn config_prefixes() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert_eq!(
+            configuration.tftp.config_prefixes,
+            vec![ConfigPrefix::pxelinux()]
+        );
+        let configuration = parse(
+            "tftp:\n  pxe: linux /Image\n  config-prefixes:\n    - path: pxelinux.cfg\n      format: syslinux\n    - path: grub\n      format: grub\n",
+        );
+        assert_eq!(
+            configuration.tftp.config_prefixes,
+            vec![
+                ConfigPrefix::pxelinux(),
+                ConfigPrefix {
+                    path: "grub".into(),
+                    format: instant_netboot::ConfigFormat::Grub,
+                    architecture: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn allow_write() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert!(!configuration.tftp.allow_write);
+        let configuration = parse("tftp:\n  pxe: linux /Image\n  allow-write: true\n");
+        assert!(configuration.tftp.allow_write);
+    }
+
+    #[test]
+    fn http() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert_eq!(configuration.http, None);
+        let configuration = parse("tftp:\n  pxe: linux /Image\nhttp:\n  socket: 0.0.0.0:8080\n");
+        assert_eq!(
+            configuration.http,
+            Some(HttpConfiguration {
+                socket: "0.0.0.0:8080".parse().unwrap()
+            })
+        );
+    }
+
+    #[test]
+    fn proxy_dhcp() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert_eq!(configuration.proxy_dhcp().unwrap(), None);
+
+        let configuration = parse(
+            "tftp:\n  pxe: linux /Image\n  next-server: 192.168.1.10\nproxy-dhcp:\n  boot-file: pxelinux.0\n  uefi-boot-file: grubx64.efi\n",
+        );
+        assert_eq!(
+            configuration.proxy_dhcp.as_ref().unwrap().socket,
+            "0.0.0.0:67".parse().unwrap()
+        );
+        assert_eq!(
+            configuration.proxy_dhcp().unwrap(),
+            Some(ProxyDhcp {
+                server: "192.168.1.10".parse().unwrap(),
+                boot_file: "pxelinux.0".to_string(),
+                uefi_boot_file: Some("grubx64.efi".to_string()),
+            })
+        );
+
+        // Clients can't be sent to 0.0.0.0
+        let configuration =
+            parse("tftp:\n  pxe: linux /Image\nproxy-dhcp:\n  boot-file: pxelinux.0\n");
+        assert!(matches!(
+            configuration.proxy_dhcp(),
+            Err(ValidationError::ProxyDhcpServerAddress(_))
+        ));
+        let configuration = parse(&format!(
+            "tftp:\n  pxe: linux /Image\n  next-server: 192.168.1.10\nproxy-dhcp:\n  boot-file: {}\n",
+            "a".repeat(128)
+        ));
+        assert!(matches!(
+            configuration.proxy_dhcp(),
+            Err(ValidationError::ProxyDhcpBootFileTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn unspecified_next_server() {
+        let entry = "  pxe: |\n    title Fedora\n    linux /Image\n    options root=/dev/nfs nfsroot=${next-server}:/srv/nfs\n";
+        let configuration = parse(&format!("tftp:\n{}", entry));
+        assert!(matches!(
+            configuration.validate(),
+            Err(ValidationError::NextServerUnspecified(_))
+        ));
+
+        let mut configuration = parse(&format!("tftp:\n  next-server: 192.168.1.10\n{}", entry));
+        configuration.on_missing_boot_file = MissingBootFilePolicy::Warn;
+        assert!(configuration.validate().is_ok());
+        // Entries that don't use the macro don't need the address
+        let mut configuration = parse("tftp:\n  pxe: |\n    title Fedora\n    linux /Image\n");
+        configuration.on_missing_boot_file = MissingBootFilePolicy::Warn;
+        assert!(configuration.validate().is_ok());
+    }
+
+    #[test]
+    fn health() {
+        let configuration = parse("tftp:\n  pxe: linux /Image\n");
+        assert_eq!(configuration.health, None);
+        let configuration =
+            parse("tftp:\n  pxe: linux /Image\nhealth:\n  socket: 127.0.0.1:8081\n");
+        assert_eq!(
+            configuration.health,
+            Some(HealthConfiguration {
+                socket: "127.0.0.1:8081".parse().unwrap()
+            })
+        );
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(Format::from_path(Path::new("netboot.toml")), Format::Toml);
+        assert_eq!(Format::from_path(Path::new("netboot.yaml")), Format::Yaml);
+        assert_eq!(Format::from_path(Path::new("netboot.yml")), Format::Yaml);
+        assert_eq!(Format::from_path(Path::new("netboot")), Format::Yaml);
+    }
+
+    #[test]
+    fn yaml_and_toml_are_equal() {
+        let yaml = r#"
+tftp:
+  socket: 0.0.0.0:69
+  next-server: 192.168.1.1
+  pxe: |
+    title Fedora 19 (Rawhide)
+    linux /Image
+    devicetree /boot.dtb
+nfs:
+  host: 192.168.1.1
+  share: /srv/nfs
+  version: 4
+  target-ip: dhcp
+  is-writable: true
+  options:
+    kernel: [nfsrootdebug]
+"#;
+        let toml = r#"
+[tftp]
+socket = "0.0.0.0:69"
+next-server = "192.168.1.1"
+pxe = """
+title Fedora 19 (Rawhide)
+linux /Image
+devicetree /boot.dtb
+"""
+
+[nfs]
+host = "192.168.1.1"
+share = "/srv/nfs"
+version = 4
+target-ip = "dhcp"
+is-writable = true
+options = { kernel = ["nfsrootdebug"] }
+"#;
+        let directory = tempfile::tempdir().unwrap();
+        let yaml_path = directory.path().join("netboot.yaml");
+        let toml_path = directory.path().join("netboot.toml");
+        std::fs::write(&yaml_path, yaml).unwrap();
+        std::fs::write(&toml_path, toml).unwrap();
+
+        let yaml = Configuration::load(&yaml_path).unwrap();
+        let toml = Configuration::load(&toml_path).unwrap();
+        assert_eq!(yaml, toml);
+        assert_eq!(
+            yaml.tftp.pxe,
+            vec![BootEntrySource::Inline(BOOT_ENTRY.parse().unwrap())]
+        );
+    }
+
+    #[test]
+    fn load_reads_entry_file() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("entry.conf"), BOOT_ENTRY).unwrap();
+        let path = directory.path().join("netboot.yaml");
+        std::fs::write(&path, "tftp:\n  pxe-file: entry.conf\n").unwrap();
+
+        let configuration = Configuration::load(&path).unwrap();
+        assert_eq!(
+            configuration.boot_entries().unwrap(),
+            vec![&BOOT_ENTRY.parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn multiple_entries() {
+        let configuration = parse(
+            "tftp:\n  default: Rescue\n  pxe:\n    - |\n      title Fedora\n      linux /Image\n    - |\n      title Rescue\n      linux /rescue/Image\n      options single\n",
+        );
+        let configuration = configuration.syslinux_configuration().unwrap();
+        assert_eq!(configuration.default.as_deref(), Some("Rescue"));
+        assert_eq!(
+            configuration.to_string(),
+            "DEFAULT Rescue\nLABEL Fedora\nKERNEL /Image\nLABEL Rescue\nKERNEL /rescue/Image\nAPPEND single\n"
+        );
+    }
+
+    #[test]
+    fn menu_labels() {
+        let configuration = parse(
+            "tftp:\n  menu-title: Lab machines\n  default: Fedora 19\n  ip-labels:\n    192.168.1.0/24: Fedora 19\n  pxe:\n    - |\n      title Fedora\n      linux /Image\n    - |\n      title Fedora 19\n      linux /19/Image\n",
+        );
+        let syslinux = configuration.syslinux_configuration().unwrap();
+        assert_eq!(
+            syslinux.to_string(),
+            "DEFAULT Fedora-19\nMENU TITLE Lab machines\nLABEL Fedora\nKERNEL /Image\nLABEL Fedora-19\nKERNEL /19/Image\nMENU LABEL Fedora 19\n"
+        );
+        assert_eq!(
+            crate::grub::render(&syslinux),
+            "set default='Fedora 19'\nmenuentry 'Fedora' {\n\tlinux '/Image'\n}\nmenuentry 'Fedora 19' {\n\tlinux '/19/Image'\n}\n"
+        );
+        assert!(configuration.resolver().is_ok());
+    }
+
+    #[test]
+    fn duplicate_titles() {
+        let configuration = parse(
+            "tftp:\n  pxe:\n    - |\n      title Fedora\n      linux /Image\n    - |\n      title Fedora\n      linux /old/Image\n",
+        );
+        assert_eq!(
+            configuration.syslinux_configuration().unwrap().to_string(),
+            "DEFAULT Fedora\nLABEL Fedora\nKERNEL /Image\nLABEL Fedora-2\nKERNEL /old/Image\nMENU LABEL Fedora\n"
+        );
+    }
+
+    #[test]
+    fn newest_entry_first() {
+        let entries = "  pxe:\n    - |\n      title Fedora 6.9\n      version 6.9.0\n      linux /6.9/Image\n    - |\n      title Fedora 6.10\n      version 6.10.0\n      linux /6.10/Image\n    - |\n      title Fedora 6.1\n      version 6.1.0\n      linux /6.1/Image\n    - |\n      title Fedora 6.11-rc1\n      version 6.11.0-rc1\n      linux /6.11/Image\n    - |\n      title Fedora 6.11\n      version 6.11.0\n      linux /6.11/Image\n";
+        let configuration = parse(&format!("tftp:\n{}", entries));
+        let syslinux = configuration.syslinux_configuration().unwrap();
+        let titles = syslinux
+            .labels
+            .iter()
+            .map(syslinux::Label::title)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            [
+                "Fedora 6.11",
+                "Fedora 6.11-rc1",
+                "Fedora 6.10",
+                "Fedora 6.9",
+                "Fedora 6.1"
+            ]
+        );
+        // A release candidate isn't the default over its release
+        assert_eq!(syslinux.default, Some("Fedora-6.11".to_string()));
+
+        let configuration = parse(&format!("tftp:\n  default: Fedora 6.1\n{}", entries));
+        assert_eq!(
+            configuration.syslinux_configuration().unwrap().default,
+            Some("Fedora-6.1".to_string())
+        );
+    }
+
+    #[test]
+    fn default_is_first_entry() {
+        let configuration = parse(
+            "tftp:\n  pxe:\n    - |\n      title Fedora\n      linux /Image\n    - |\n      title Rescue\n      linux /rescue/Image\n",
+        );
+        assert_eq!(
+            configuration.syslinux_configuration().unwrap().default,
+            Some("Fedora".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_default() {
+        let configuration =
+            parse("tftp:\n  default: Rescue\n  pxe: |\n    title Fedora\n    linux /Image\n");
+        assert!(matches!(
+            configuration.syslinux_configuration(),
+            Err(ValidationError::UnknownDefault(default)) if default == "Rescue"
+        ));
+    }
+
+    #[test]
+    fn ip_labels() {
+        use crate::{instant_netboot::ClientId, resolver::ConfigResolver};
+
+        let configuration = parse(
+            "tftp:\n  ip-labels:\n    192.168.1.0/24: Rescue\n  pxe:\n    - |\n      title Fedora\n      linux /Image\n    - |\n      title Rescue\n      linux /rescue/Image\n",
+        );
+        let resolver = configuration.resolver().unwrap();
+        let client = ClientId::Ip("192.168.1.1".parse().unwrap());
+        assert_eq!(
+            resolver.resolve(&client).unwrap().to_string(),
+            "DEFAULT Rescue\nLABEL Rescue\nKERNEL /rescue/Image\n"
+        );
+        assert_eq!(
+            resolver
+                .resolve(&ClientId::Default)
+                .unwrap()
+                .default
+                .as_deref(),
+            Some("Fedora")
+        );
+    }
+
+    #[test]
+    fn architecture_prefixes() {
+        use crate::instant_netboot::{ClientId, NetbootServer};
+        use async_std::task::block_on;
+        use futures::AsyncReadExt;
+
+        let configuration = parse(
+            "tftp:\n  config-prefixes:\n    - path: grub-arm64\n      format: grub\n      architecture: aa64\n    - path: grub-x86\n      format: grub\n      architecture: x64\n    - path: pxelinux.cfg\n      format: syslinux\n  pxe:\n    - |\n      title Linux\n      architecture aa64\n      linux /arm64/Image\n    - |\n      title Linux\n      architecture X64\n      linux /x86/bzImage\n    - |\n      title Rescue\n      linux /rescue/Image\n",
+        );
+        let server = NetbootServer::with_resolver(
+            Box::new(configuration.resolver().unwrap()),
+            Default::default(),
+        )
+        .with_config_prefixes(configuration.tftp.config_prefixes.clone());
+        let get = |path: &str| {
+            block_on(async {
+                let mut contents = String::new();
+                let mut reader = server.tftp_get(Path::new(path)).await.unwrap();
+                reader.read_to_string(&mut contents).await.unwrap();
+                contents
+            })
+        };
+
+        let arm64 = get("grub-arm64/grub.cfg-01-88-99-aa-bb-cc-dd");
+        assert!(arm64.contains("linux '/arm64/Image'"), "{}", arm64);
+        assert!(!arm64.contains("bzImage"), "{}", arm64);
+        assert!(arm64.contains("linux '/rescue/Image'"), "{}", arm64);
+        let x86 = get("grub-x86/grub.cfg");
+        assert!(x86.contains("linux '/x86/bzImage'"), "{}", x86);
+        assert!(!x86.contains("/arm64/Image"), "{}", x86);
+        // Both kernels are served, whichever prefix the client used
+        assert_eq!(
+            block_on(server.render_config_for(&ClientId::Default))
+                .unwrap()
+                .labels
+                .len(),
+            3
+        );
+        assert!(get("pxelinux.cfg/default").contains("/arm64/Image"));
+    }
+
+    #[test]
+    fn no_entries_for_architecture() {
+        let configuration = parse(
+            "tftp:\n  config-prefixes:\n    - path: grub-riscv\n      format: grub\n      architecture: riscv64\n  pxe: |\n    title Linux\n    architecture x64\n    linux /bzImage\n",
+        );
+        assert!(matches!(
+            configuration.resolver(),
+            Err(ValidationError::NoEntriesForArchitecture(architecture)) if architecture == "riscv64"
+        ));
+    }
+
+    #[test]
+    fn unknown_ip_label() {
+        let configuration = parse(
+            "tftp:\n  ip-labels:\n    192.168.1.5: Rescue\n  pxe: |\n    title Fedora\n    linux /Image\n",
+        );
+        assert!(matches!(
+            configuration.resolver(),
+            Err(ValidationError::UnknownIpLabel(_, name)) if name == "Rescue"
+        ));
+    }
+
+    #[test]
+    fn empty_entry_list() {
+        assert!(serde_yaml::from_str::<Configuration>("tftp:\n  pxe: []\n").is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        std::fs::write(&kernel, "").unwrap();
+
+        let configuration = parse(&format!(
+            "tftp:\n  pxe: |\n    title Test\n    linux {}\n",
+            kernel.display()
+        ));
+        configuration.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_missing_kernel_entry() {
+        let configuration = parse("tftp:\n  pxe: title Test\n");
+        assert!(matches!(
+            configuration.validate(),
+            Err(ValidationError::InvalidBootEntry(
+                syslinux::ConfigurationConversionError::MissingKernel
+            ))
+        ));
+    }
+
+    #[test]
+    fn validate_nonexistent_boot_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+
+        let configuration = parse(&format!(
+            "tftp:\n  pxe: |\n    title Test\n    linux {}\n",
+            kernel.display()
+        ));
+        match configuration.validate() {
+            Err(ValidationError::MissingBootFile(path)) => assert_eq!(path, kernel),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn missing_boot_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        std::fs::write(&kernel, "").unwrap();
+        let fdt = directory.path().join("boot.dtb");
+
+        let configuration = |policy: &str| {
+            parse(&format!(
+                "tftp:\n  pxe:\n    - |\n      title A\n      linux {0}\n      devicetree {1}\n    - |\n      title B\n      linux {0}\n      devicetree {1}\non-missing-boot-file: {2}\n",
+                kernel.display(),
+                fdt.display(),
+                policy
+            ))
+        };
+        assert_eq!(
+            configuration("fail").missing_boot_files().unwrap(),
+            vec![fdt.clone()]
+        );
+        match configuration("fail").validate() {
+            Err(ValidationError::MissingBootFile(path)) => assert_eq!(path, fdt),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        configuration("warn").validate().unwrap();
+
+        std::fs::write(&fdt, "").unwrap();
+        assert!(configuration("fail")
+            .missing_boot_files()
+            .unwrap()
+            .is_empty());
+        configuration("fail").validate().unwrap();
+    }
+
+    #[test]
+    fn validate_relative_nfs_share() {
+        let configuration = parse(
+            "tftp:\n  pxe: |\n    title Test\n    linux /\nnfs:\n  host: 192.168.1.1\n  share: srv/nfs\n  version: 4\n  target-ip: dhcp\n  is-writable: false\n",
+        );
+        match configuration.validate() {
+            Err(ValidationError::RelativeNfsShare(path)) => assert_eq!(path, Path::new("srv/nfs")),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn validate_nfs_share() {
+        let configuration = |share: &str| {
+            parse(&format!(
+                "tftp:\n  pxe: |\n    title Test\n    linux /\nnfs:\n  host: 192.168.1.1\n  share: \"{}\"\n  version: 3\n  target-ip: dhcp\n  is-writable: false\n",
+                share
+            ))
+        };
+        configuration("/srv/nfs").validate().unwrap();
+        assert!(matches!(
+            configuration("").validate(),
+            Err(ValidationError::EmptyNfsShare)
+        ));
+        let error = configuration("srv/nfs").validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "nfs.share srv/nfs is not an absolute path"
+        );
+    }
+
+    #[test]
+    fn nfs_shares_by_label() {
+        let configuration = parse(
+            "tftp:\n  pxe: linux /Image\nnfs:\n  - host: 192.168.1.1\n    share: /srv/nfs\n    version: 3\n    target-ip: dhcp\n    is-writable: false\n  - label: Rescue\n    host: 192.168.1.1\n    share: /srv/rescue\n    version: 4.1\n    target-ip: dhcp\n    is-writable: true\n",
+        );
+        let nfs = configuration.nfs.unwrap();
+        assert_eq!(
+            nfs.for_label("Fedora").unwrap().share,
+            Path::new("/srv/nfs")
+        );
+        assert_eq!(
+            nfs.for_label("Rescue").unwrap().share,
+            Path::new("/srv/rescue")
+        );
+    }
+
+    #[test]
+    fn nfs_shares_duplicate_label() {
+        let share = "host: 192.168.1.1\n    share: /srv/nfs\n    version: 3\n    target-ip: dhcp\n    is-writable: false\n";
+        let yaml = format!(
+            "tftp:\n  pxe: linux /Image\nnfs:\n  - label: Rescue\n    {}\n  - label: Rescue\n    {}",
+            share, share
+        );
+        assert!(serde_yaml::from_str::<Configuration>(&yaml).is_err());
+        let yaml = format!(
+            "tftp:\n  pxe: linux /Image\nnfs:\n  - {}\n  - {}",
+            share, share
+        );
+        assert!(serde_yaml::from_str::<Configuration>(&yaml).is_err());
+    }
+
+    #[test]
+    fn validate_unknown_nfs_label() {
+        let configuration = parse(
+            "tftp:\n  pxe: |\n    title Test\n    linux /\nnfs:\n  - label: Rescue\n    host: 192.168.1.1\n    share: /srv/nfs\n    version: 3\n    target-ip: dhcp\n    is-writable: false\n",
+        );
+        assert!(matches!(
+            configuration.validate(),
+            Err(ValidationError::UnknownNfsLabel(name)) if name == "Rescue"
+        ));
+    }
+
+    #[test]
+    fn validate_nfs_v4_share() {
+        let configuration = |version: &str| {
+            parse(&format!(
+                "tftp:\n  pxe: |\n    title Test\n    linux /\nnfs:\n  host: 192.168.1.1\n  share: /srv/../nfs\n  version: {}\n  target-ip: dhcp\n  is-writable: false\n",
+                version
+            ))
+        };
+        configuration("3").validate().unwrap();
+        match configuration("4.1").validate() {
+            Err(ValidationError::InvalidNfsV4Share(path)) => {
+                assert_eq!(path, Path::new("/srv/../nfs"))
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        (name == "ROOT_UUID").then(|| "6d3376e4".to_string())
+    }
+
+    #[test]
+    fn expand_defined_variable() {
+        let mut configuration = parse(
+            "tftp:\n  pxe: |\n    title Test\n    linux /Image\n    options root=UUID=${ROOT_UUID} quiet\nnfs:\n  host: 192.168.1.1\n  share: /srv/${ROOT_UUID}\n  version: 3\n  target-ip: dhcp\n  is-writable: false\n",
+        );
+        configuration.expand_variables(lookup).unwrap();
+        assert_eq!(
+            configuration.boot_entries().unwrap()[0].to_string(),
+            "title Test\nlinux /Image\noptions root=UUID=6d3376e4 quiet\n"
+        );
+        assert_eq!(
+            configuration.nfs.unwrap().default.unwrap().share,
+            Path::new("/srv/6d3376e4")
+        );
+    }
+
+    #[test]
+    fn expand_undefined_variable() {
+        let mut configuration =
+            parse("tftp:\n  pxe: |\n    title Test\n    linux /Image\n    options root=${ROOT}\n");
+        assert!(matches!(
+            configuration.expand_variables(lookup),
+            Err(Error::UndefinedVariable(name)) if name == "ROOT"
+        ));
+    }
+
+    #[test]
+    fn expand_escaped_variable() {
+        assert_eq!(
+            expand_variables("a=$${ROOT_UUID} b=${ROOT_UUID} c=${next-server}", lookup).unwrap(),
+            "a=${ROOT_UUID} b=6d3376e4 c=${next-server}"
+        );
+    }
+
+    #[test]
+    fn expand_environment_is_opt_in() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("netboot.yaml");
+        let contents = "tftp:\n  pxe: |\n    title Test\n    linux /Image\n    options root=${INSTANT_NETBOOT_UNSET_VARIABLE}\n";
+        std::fs::write(&path, contents).unwrap();
+        assert!(!Configuration::load(&path).unwrap().expand_environment);
+
+        std::fs::write(&path, format!("expand-environment: true\n{}", contents)).unwrap();
+        assert!(matches!(
+            Configuration::load(&path),
+            Err(Error::UndefinedVariable(name)) if name == "INSTANT_NETBOOT_UNSET_VARIABLE"
+        ));
+    }
+
+    #[test]
+    fn invalid_file_entry_names_file() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("entry.conf"), "linux /Image\nfoo\n").unwrap();
+
+        let configuration = parse("tftp:\n  pxe:\n    file: entry.conf\n");
+        let error = configuration.tftp.pxe[0]
+            .clone()
+            .load(directory.path())
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidBootEntry(..)));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "invalid boot entry in {}",
+                directory.path().join("entry.conf").display()
+            )
+        );
+    }
 }
@@ -0,0 +1,130 @@
+use std::{fmt::Write, path::Path};
+
+use boot_loader_entries::{syslinux, uapi};
+
+/// Quote a string for a GRUB script
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quote a path for a GRUB script
+fn quote_path(path: &Path) -> String {
+    quote(&path.to_string_lossy())
+}
+
+/// Render a configuration as a GRUB script, with a menu entry for each label, named by its title.
+/// Directives that GRUB has no equivalent for (device tree overlays and directories, IPAPPEND) and
+/// the menu title are left out.
+pub(crate) fn render(configuration: &syslinux::Configuration) -> String {
+    let mut script = String::new();
+    let default = configuration.default.as_ref().map(|default| {
+        configuration
+            .labels
+            .iter()
+            .find(|label| label.name == *default)
+            .map_or(default.as_str(), |label| label.title())
+    });
+    if let Some(default) = default {
+        writeln!(script, "set default={}", quote(default)).unwrap();
+    }
+    for label in &configuration.labels {
+        writeln!(script, "menuentry {} {{", quote(label.title())).unwrap();
+        let options = label
+            .directives
+            .iter()
+            .find_map(|directive| match directive {
+                syslinux::LabelDirective::Append(options) => Some(uapi::join_options(options)),
+                _ => None,
+            });
+        match &label.kernel {
+            syslinux::Kernel::Kernel(image) => match &options {
+                Some(options) => writeln!(script, "\tlinux {} {}", quote_path(image), options),
+                None => writeln!(script, "\tlinux {}", quote_path(image)),
+            }
+            .unwrap(),
+            // Returning to the firmware boots the next device
+            syslinux::Kernel::LocalBoot(_) => writeln!(script, "\texit").unwrap(),
+        }
+        // GRUB only loads the last initrd command, so every initrd goes in one
+        let initrds = label
+            .directives
+            .iter()
+            .filter_map(|directive| match directive {
+                syslinux::LabelDirective::Initrd(initrd) => Some(quote_path(initrd)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !initrds.is_empty() {
+            writeln!(script, "\tinitrd {}", initrds.join(" ")).unwrap();
+        }
+        for directive in &label.directives {
+            match directive {
+                syslinux::LabelDirective::Fdt(fdt) => {
+                    writeln!(script, "\tdevicetree {}", quote_path(fdt)).unwrap()
+                }
+                syslinux::LabelDirective::Initrd(_)
+                | syslinux::LabelDirective::FdtOverlays(_)
+                | syslinux::LabelDirective::FdtDir(_)
+                | syslinux::LabelDirective::IpAppend(_)
+                | syslinux::LabelDirective::Append(_)
+                | syslinux::LabelDirective::MenuLabel(_) => {}
+            }
+        }
+        writeln!(script, "}}").unwrap();
+    }
+    script
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_configuration() {
+        let configuration = syslinux::Configuration {
+            default: Some("Fedora".to_string()),
+            menu_title: None,
+            labels: vec![
+                syslinux::Label {
+                    name: "Fedora".to_string(),
+                    kernel: syslinux::Kernel::Kernel("/Image".into()),
+                    directives: vec![
+                        syslinux::LabelDirective::Initrd("/initrd.img".into()),
+                        syslinux::LabelDirective::Fdt("/boot.dtb".into()),
+                        syslinux::LabelDirective::Initrd("/Bob's firmware.img".into()),
+                        syslinux::LabelDirective::Append(vec![
+                            "quiet".to_string(),
+                            "ro".to_string(),
+                        ]),
+                        syslinux::LabelDirective::IpAppend(2),
+                    ],
+                },
+                syslinux::Label {
+                    name: "Rescue".to_string(),
+                    kernel: syslinux::Kernel::Kernel("/rescue/My Image".into()),
+                    directives: vec![],
+                },
+                syslinux::Label {
+                    name: "Bob's disk".to_string(),
+                    kernel: syslinux::Kernel::LocalBoot(0),
+                    directives: vec![],
+                },
+            ],
+        };
+        assert_eq!(
+            render(&configuration),
+            "set default='Fedora'\n\
+             menuentry 'Fedora' {\n\
+             \tlinux '/Image' quiet ro\n\
+             \tinitrd '/initrd.img' '/Bob'\\''s firmware.img'\n\
+             \tdevicetree '/boot.dtb'\n\
+             }\n\
+             menuentry 'Rescue' {\n\
+             \tlinux '/rescue/My Image'\n\
+             }\n\
+             menuentry 'Bob'\\''s disk' {\n\
+             \texit\n\
+             }\n"
+        );
+    }
+}
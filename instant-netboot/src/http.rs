@@ -0,0 +1,673 @@
+use std::{
+    fmt,
+    io::{self, SeekFrom},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_std::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+};
+use futures::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, StreamExt,
+};
+use tracing::{field, Instrument};
+
+use crate::{
+    instant_netboot::{self, NetbootServer},
+    shutdown,
+};
+
+/// The longest request line and headers accepted from a client
+const MAX_REQUEST_HEAD: u64 = 8192;
+
+/// The status of a response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Ok,
+    PartialContent,
+    BadRequest,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RangeNotSatisfiable,
+    InternalServerError,
+    ServiceUnavailable,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "200 OK",
+            Status::PartialContent => "206 Partial Content",
+            Status::BadRequest => "400 Bad Request",
+            Status::Forbidden => "403 Forbidden",
+            Status::NotFound => "404 Not Found",
+            Status::MethodNotAllowed => "405 Method Not Allowed",
+            Status::RangeNotSatisfiable => "416 Range Not Satisfiable",
+            Status::InternalServerError => "500 Internal Server Error",
+            Status::ServiceUnavailable => "503 Service Unavailable",
+        })
+    }
+}
+
+impl From<instant_netboot::Error> for Status {
+    fn from(value: instant_netboot::Error) -> Self {
+        match value {
+            instant_netboot::Error::InvalidRequestPath => Status::BadRequest,
+            instant_netboot::Error::FileNotFound => Status::NotFound,
+            instant_netboot::Error::AccessDenied => Status::Forbidden,
+            instant_netboot::Error::IoError(_) => Status::InternalServerError,
+        }
+    }
+}
+
+/// The parts of a request we care about
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: PathBuf,
+    /// The value of the Range header, if any
+    range: Option<String>,
+}
+
+/// A single range of bytes requested by a client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// From the first offset to the second (inclusive), or to the end of the file
+    FromTo(u64, Option<u64>),
+    /// The last N bytes of the file
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parse the value of a Range header. Returns None if the header isn't a single byte range, in
+    /// which case it's ignored and the whole file is served.
+    fn parse(value: &str) -> Option<Self> {
+        let (first, last) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+        match (first.is_empty(), last.is_empty()) {
+            (true, false) => Some(ByteRange::Suffix(last.parse().ok()?)),
+            (false, true) => Some(ByteRange::FromTo(first.parse().ok()?, None)),
+            (false, false) => {
+                let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+                (first <= last).then_some(ByteRange::FromTo(first, Some(last)))
+            }
+            (true, true) => None,
+        }
+    }
+
+    /// The first and last offsets (inclusive) of this range in a file of `size` bytes. Returns
+    /// None if the range is unsatisfiable, which every range of an empty file is.
+    fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        match *self {
+            _ if size == 0 => None,
+            ByteRange::FromTo(first, _) if first >= size => None,
+            ByteRange::FromTo(first, last) => Some((first, last.unwrap_or(size - 1).min(size - 1))),
+            ByteRange::Suffix(0) => None,
+            ByteRange::Suffix(length) => Some((size.saturating_sub(length), size - 1)),
+        }
+    }
+}
+
+/// The Content-Disposition header for a boot file, so that a browser saves it under its own name.
+/// Returns None if the path has no UTF-8 file name.
+fn content_disposition(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+    Some(format!("attachment; filename=\"{}\"", name))
+}
+
+/// Decode %XX escapes in a request target. Returns None if an escape is malformed, or the result
+/// isn't UTF-8.
+fn percent_decode(input: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut input = input.as_bytes().iter();
+    while let Some(&byte) = input.next() {
+        if byte == b'%' {
+            let digits = [*input.next()?, *input.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The path of the file requested by this request target. PXE configurations are served at
+/// `/pxelinux.cfg/*`, like they are over TFTP. Any other path is looked up as given, so boot files
+/// are served at their path in the boot entry.
+fn request_path(target: &str) -> Option<PathBuf> {
+    let target = target.split(['?', '#']).next()?;
+    let path = PathBuf::from(percent_decode(target)?);
+    match path.strip_prefix("/") {
+        Ok(relative) if relative.starts_with("pxelinux.cfg") => Some(relative.into()),
+        _ => Some(path),
+    }
+}
+
+/// Read the request line and headers of a request
+async fn read_request(reader: impl AsyncRead + Unpin) -> Result<Request, Status> {
+    let mut lines = BufReader::new(reader.take(MAX_REQUEST_HEAD)).lines();
+    let line = match lines.next().await {
+        Some(Ok(line)) => line,
+        _ => return Err(Status::BadRequest),
+    };
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Status::BadRequest);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Status::BadRequest);
+    }
+    let mut request = Request {
+        method: method.to_string(),
+        path: request_path(target).ok_or(Status::BadRequest)?,
+        range: None,
+    };
+
+    // The headers end with an empty line
+    loop {
+        match lines.next().await {
+            Some(Ok(line)) if line.is_empty() => return Ok(request),
+            Some(Ok(line)) => match line.split_once(':') {
+                Some((name, value)) if name.eq_ignore_ascii_case("range") => {
+                    request.range = Some(value.trim().to_string())
+                }
+                Some(_) => {}
+                None => return Err(Status::BadRequest),
+            },
+            _ => return Err(Status::BadRequest),
+        }
+    }
+}
+
+/// Write the status line and headers of a response
+async fn write_head(
+    writer: &mut (impl AsyncWrite + Unpin),
+    status: Status,
+    headers: &[(&str, String)],
+) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes()).await
+}
+
+/// Respond to a request that failed
+async fn write_error(writer: &mut (impl AsyncWrite + Unpin), status: Status) -> io::Result<()> {
+    let body = format!("{}\n", status);
+    let mut headers = vec![
+        ("Content-Type", "text/plain".to_string()),
+        ("Content-Length", body.len().to_string()),
+    ];
+    if status == Status::MethodNotAllowed {
+        headers.push(("Allow", "GET, HEAD".to_string()));
+    }
+    write_head(writer, status, &headers).await?;
+    writer.write_all(body.as_bytes()).await
+}
+
+/// Serve a single request, then close the connection
+async fn handle(
+    stream: TcpStream,
+    client: SocketAddr,
+    server: &NetbootServer,
+    transfers: &shutdown::Transfers,
+) -> io::Result<()> {
+    let mut writer = &stream;
+    let request = match read_request(&stream).await {
+        Ok(request) => request,
+        Err(status) => return write_error(&mut writer, status).await,
+    };
+
+    let span = tracing::debug_span!(
+        "request",
+        %client,
+        path = %request.path.display(),
+        action = field::Empty,
+        bytes = field::Empty,
+    );
+    tracing::debug!(parent: &span, "{}", request.method);
+    if request.method != "GET" && request.method != "HEAD" {
+        return write_error(&mut writer, Status::MethodNotAllowed).await;
+    }
+    let Some(_transfer) = transfers.start() else {
+        return write_error(&mut writer, Status::ServiceUnavailable).await;
+    };
+
+    let (mut reader, size) = match server
+        .tftp_open(&request.path)
+        .instrument(span.clone())
+        .await
+    {
+        Ok(file) => file,
+        Err(error) => {
+            if let instant_netboot::Error::AccessDenied = error {
+                span.record("action", "denied");
+                tracing::warn!(parent: &span, "denied {}", request.method);
+            }
+            return write_error(&mut writer, error.into()).await;
+        }
+    };
+    // Generated configurations may not have a size, so find the end of the file
+    let size = match size {
+        Some(size) => size,
+        None => {
+            let size = reader.seek(SeekFrom::End(0)).await?;
+            reader.seek(SeekFrom::Start(0)).await?;
+            size
+        }
+    };
+
+    let is_config = server.is_config_path(&request.path);
+    let content_type = match is_config {
+        true => "text/plain",
+        false => "application/octet-stream",
+    };
+    let mut headers = vec![("Content-Type", content_type.to_string())];
+    if let Some(disposition) = content_disposition(&request.path).filter(|_| !is_config) {
+        headers.push(("Content-Disposition", disposition));
+    }
+    headers.push(("Accept-Ranges", "bytes".to_string()));
+    let range = request.range.as_deref().and_then(ByteRange::parse);
+    let (status, first, length) = match range.map(|range| range.resolve(size)) {
+        Some(Some((first, last))) => {
+            headers.push((
+                "Content-Range",
+                format!("bytes {}-{}/{}", first, last, size),
+            ));
+            (Status::PartialContent, first, last - first + 1)
+        }
+        Some(None) => {
+            let headers = [
+                ("Content-Range", format!("bytes */{}", size)),
+                ("Content-Length", "0".to_string()),
+            ];
+            return write_head(&mut writer, Status::RangeNotSatisfiable, &headers).await;
+        }
+        None => (Status::Ok, 0, size),
+    };
+    headers.push(("Content-Length", length.to_string()));
+
+    write_head(&mut writer, status, &headers).await?;
+    if request.method == "GET" {
+        reader.seek(SeekFrom::Start(first)).await?;
+        let bytes = futures::io::copy(reader.take(length), &mut writer).await?;
+        span.record("bytes", bytes);
+    }
+    writer.flush().await
+}
+
+/// Serve the same files as the TFTP server over HTTP, for UEFI HTTP Boot. Configurations are
+/// served from the same prefixes (e.g. `/pxelinux.cfg/*`), and boot files at their path in the
+/// boot entry.
+pub async fn serve(
+    listener: TcpListener,
+    server: Arc<NetbootServer>,
+    transfers: shutdown::Transfers,
+) -> anyhow::Result<()> {
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = stream?;
+        let client = stream.peer_addr()?;
+        let server = server.clone();
+        let transfers = transfers.clone();
+        async_std::task::spawn(async move {
+            if let Err(error) = handle(stream, client, &server, &transfers).await {
+                tracing::debug!("HTTP request from {} failed: {}", client, error);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer a health check. Any GET request is answered with 200 and the number of boot labels.
+async fn handle_health(stream: TcpStream, labels: usize) -> io::Result<()> {
+    let mut writer = &stream;
+    let request = match read_request(&stream).await {
+        Ok(request) => request,
+        Err(status) => return write_error(&mut writer, status).await,
+    };
+    if request.method != "GET" && request.method != "HEAD" {
+        return write_error(&mut writer, Status::MethodNotAllowed).await;
+    }
+
+    let body = format!("OK\nlabels: {}\n", labels);
+    let headers = [
+        ("Content-Type", "text/plain".to_string()),
+        ("Content-Length", body.len().to_string()),
+    ];
+    write_head(&mut writer, Status::Ok, &headers).await?;
+    if request.method == "GET" {
+        writer.write_all(body.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Serve health checks, for orchestrators. This is only started once the configuration has been
+/// validated and the other servers are listening, so any answer means the server is ready.
+pub async fn serve_health(listener: TcpListener, labels: usize) -> anyhow::Result<()> {
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = stream?;
+        async_std::task::spawn(async move {
+            if let Err(error) = handle_health(stream, labels).await {
+                tracing::debug!("Health check failed: {}", error);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::task::block_on;
+    use boot_loader_entries::syslinux;
+
+    /// Send a request to the server, returning the response
+    async fn request(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// A server for a single label, booting `kernel`
+    fn server(kernel: &Path) -> NetbootServer {
+        NetbootServer::new(
+            syslinux::Label {
+                name: "test".to_string(),
+                kernel: syslinux::Kernel::Kernel(kernel.into()),
+                directives: vec![],
+            }
+            .into(),
+        )
+    }
+
+    /// Serve `server` while running `client`
+    fn with_server<F, T>(server: NetbootServer, client: impl FnOnce(SocketAddr) -> F) -> T
+    where
+        F: std::future::Future<Output = T>,
+    {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let serve = serve(listener, server.into(), shutdown::Transfers::default());
+            let client = client(address);
+            futures::pin_mut!(serve, client);
+            match futures::future::select(serve, client).await {
+                futures::future::Either::Right((result, _)) => result,
+                futures::future::Either::Left((result, _)) => {
+                    panic!("server exited: {:?}", result)
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn request_path() {
+        assert_eq!(
+            super::request_path("/pxelinux.cfg/default"),
+            Some("pxelinux.cfg/default".into())
+        );
+        assert_eq!(
+            super::request_path("/boot/vmlinuz%2D6.1?x=1"),
+            Some("/boot/vmlinuz-6.1".into())
+        );
+        assert_eq!(super::request_path("/boot/%zz"), None);
+    }
+
+    #[test]
+    fn serve_config_and_boot_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        std::fs::write(&kernel, "kernel").unwrap();
+        let server = server(&kernel);
+        let configuration = format!("LABEL test\nKERNEL {}\n", kernel.display());
+
+        let (config, boot_file, head, missing, post) = with_server(server, |address| async move {
+            (
+                request(
+                    address,
+                    "GET /pxelinux.cfg/default HTTP/1.1\r\nHost: x\r\n\r\n",
+                )
+                .await,
+                request(
+                    address,
+                    &format!("GET {} HTTP/1.1\r\n\r\n", kernel.display()),
+                )
+                .await,
+                request(
+                    address,
+                    &format!("HEAD {} HTTP/1.1\r\n\r\n", kernel.display()),
+                )
+                .await,
+                request(address, "GET /missing HTTP/1.1\r\n\r\n").await,
+                request(address, "POST /pxelinux.cfg/default HTTP/1.1\r\n\r\n").await,
+            )
+        });
+
+        assert_eq!(
+            config,
+            format!(
+                "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/plain\r\nAccept-Ranges: bytes\r\nContent-Length: {}\r\n\r\n{}",
+                configuration.len(),
+                configuration
+            )
+        );
+        assert_eq!(
+            boot_file,
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=\"Image\"\r\nAccept-Ranges: bytes\r\nContent-Length: 6\r\n\r\nkernel"
+        );
+        assert_eq!(
+            head,
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=\"Image\"\r\nAccept-Ranges: bytes\r\nContent-Length: 6\r\n\r\n"
+        );
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(post.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(post.contains("\r\nAllow: GET, HEAD\r\n"));
+    }
+
+    #[test]
+    fn serve_large_boot_file() {
+        const SIZE: u64 = 128 * 1024 * 1024;
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        // Sparse, so that the test doesn't need the disk space either
+        std::fs::File::create(&kernel)
+            .unwrap()
+            .set_len(SIZE)
+            .unwrap();
+        let server = server(&kernel);
+
+        let (head, body) = with_server(server, |address| async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\n\r\n", kernel.display());
+            stream.write_all(request.as_bytes()).await.unwrap();
+            // The body is counted rather than kept, as it arrives
+            let mut head = Vec::new();
+            let mut body = 0;
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let length = stream.read(&mut buffer).await.unwrap();
+                if length == 0 {
+                    break (String::from_utf8(head).unwrap(), body);
+                }
+                let mut data = &buffer[..length];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let Some((&byte, rest)) = data.split_first() else {
+                        break;
+                    };
+                    head.push(byte);
+                    data = rest;
+                }
+                body += data.len() as u64;
+            }
+        });
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains(&format!("\r\nContent-Length: {}\r\n", SIZE)));
+        assert_eq!(body, SIZE);
+    }
+
+    #[test]
+    fn content_disposition() {
+        assert_eq!(
+            super::content_disposition(Path::new("/boot/vmlinuz-6.1")),
+            Some("attachment; filename=\"vmlinuz-6.1\"".to_string())
+        );
+        assert_eq!(
+            super::content_disposition(Path::new("/boot/a \"b\"\\c")),
+            Some("attachment; filename=\"a \\\"b\\\"\\\\c\"".to_string())
+        );
+        assert_eq!(super::content_disposition(Path::new("/")), None);
+    }
+
+    #[test]
+    fn byte_range() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-99"),
+            Some(ByteRange::FromTo(0, Some(99)))
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=100-"),
+            Some(ByteRange::FromTo(100, None))
+        );
+        assert_eq!(ByteRange::parse("bytes=-100"), Some(ByteRange::Suffix(100)));
+        for value in [
+            "bytes=0-1,4-5",
+            "bytes=5-4",
+            "bytes=-",
+            "items=0-1",
+            "bytes=a-b",
+        ] {
+            assert_eq!(ByteRange::parse(value), None, "{}", value);
+        }
+
+        assert_eq!(ByteRange::FromTo(2, Some(99)).resolve(10), Some((2, 9)));
+        assert_eq!(ByteRange::FromTo(2, None).resolve(10), Some((2, 9)));
+        assert_eq!(ByteRange::FromTo(10, None).resolve(10), None);
+        assert_eq!(ByteRange::Suffix(3).resolve(10), Some((7, 9)));
+        assert_eq!(ByteRange::Suffix(100).resolve(10), Some((0, 9)));
+        assert_eq!(ByteRange::Suffix(0).resolve(10), None);
+        assert_eq!(ByteRange::Suffix(3).resolve(0), None);
+        assert_eq!(ByteRange::FromTo(0, None).resolve(0), None);
+        assert_eq!(ByteRange::FromTo(0, Some(9)).resolve(0), None);
+    }
+
+    #[test]
+    fn range_requests() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        let contents = (0..1000)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect::<String>();
+        std::fs::write(&kernel, &contents).unwrap();
+        let server = server(&kernel);
+
+        let get = |range: &str| {
+            format!(
+                "GET {} HTTP/1.1\r\nRange: {}\r\n\r\n",
+                kernel.display(),
+                range
+            )
+        };
+        let requests = [
+            get("bytes=10-19"),
+            get("bytes=-100"),
+            get("bytes=1000-"),
+            get("bytes=0-1, 5-6"),
+        ];
+        let responses = with_server(server, |address| async move {
+            let mut responses = Vec::new();
+            for request_text in &requests {
+                responses.push(request(address, request_text).await);
+            }
+            responses
+        });
+
+        let head = |status: &str, headers: &str| {
+            format!(
+                "HTTP/1.1 {}\r\nConnection: close\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=\"Image\"\r\nAccept-Ranges: bytes\r\n{}\r\n",
+                status, headers
+            )
+        };
+        assert_eq!(
+            responses[0],
+            head(
+                "206 Partial Content",
+                "Content-Range: bytes 10-19/1000\r\nContent-Length: 10\r\n"
+            ) + &contents[10..20]
+        );
+        assert_eq!(
+            responses[1],
+            head(
+                "206 Partial Content",
+                "Content-Range: bytes 900-999/1000\r\nContent-Length: 100\r\n"
+            ) + &contents[900..]
+        );
+        assert_eq!(
+            responses[2],
+            "HTTP/1.1 416 Range Not Satisfiable\r\nConnection: close\r\nContent-Range: bytes */1000\r\nContent-Length: 0\r\n\r\n"
+        );
+        assert_eq!(
+            responses[3],
+            head("200 OK", "Content-Length: 1000\r\n") + &contents
+        );
+    }
+
+    #[test]
+    fn health() {
+        let responses = block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let serve = serve_health(listener, 2);
+            let client = async {
+                let mut responses = Vec::new();
+                for request_text in [
+                    "GET /health HTTP/1.1\r\n\r\n",
+                    "HEAD / HTTP/1.1\r\n\r\n",
+                    "POST /health HTTP/1.1\r\n\r\n",
+                ] {
+                    responses.push(request(address, request_text).await);
+                }
+                responses
+            };
+            futures::pin_mut!(serve, client);
+            match futures::future::select(serve, client).await {
+                futures::future::Either::Right((result, _)) => result,
+                futures::future::Either::Left((result, _)) => {
+                    panic!("server exited: {:?}", result)
+                }
+            }
+        });
+
+        assert_eq!(
+            responses[0],
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/plain\r\nContent-Length: 13\r\n\r\nOK\nlabels: 2\n"
+        );
+        assert_eq!(
+            responses[1],
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/plain\r\nContent-Length: 13\r\n\r\n"
+        );
+        assert!(responses[2].starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn bad_request() {
+        let server = server(Path::new("/Image"));
+        let response = with_server(server, |address| async move {
+            request(address, "GET /pxelinux.cfg/default\r\n\r\n").await
+        });
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
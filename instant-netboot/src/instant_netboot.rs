@@ -1,23 +1,99 @@
 use std::{
-    borrow::Cow,
-    cell::LazyCell,
-    net::IpAddr,
-    path::{Path, PathBuf},
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr},
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock,
+    },
+    task::{Context, Poll},
 };
 
 use async_std::fs::File;
 use boot_loader_entries::{syslinux, BootFile};
-use futures::AsyncRead;
+use futures::{io::BufReader, ready, AsyncRead, AsyncSeek};
 use regex::Regex;
-use serde::Deserialize;
+use serde::{de, Deserialize};
+
+use crate::{grub, resolver::ConfigResolver};
 
 /// The NFS version to configure the target for
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NfsVersion {
-    #[serde(rename = "3")]
     NFSv3,
-    #[serde(rename = "4")]
-    NFSv4,
+    /// NFSv4, optionally with a minor version (e.g. 4.1). Without one, the minor version is
+    /// negotiated with the server.
+    NFSv4 {
+        minor: Option<u8>,
+    },
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("unsupported NFS version: \"{0}\"")]
+pub struct UnsupportedNfsVersion(String);
+
+impl FromStr for NfsVersion {
+    type Err = UnsupportedNfsVersion;
+
+    /// Accepts the version number, optionally prefixed by "v", "nfs" or "nfsv" (e.g. "3", "v3",
+    /// "nfs4", "NFSv4.1").
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let lowercase = input.to_ascii_lowercase();
+        let version = lowercase
+            .strip_prefix("nfsv")
+            .or_else(|| lowercase.strip_prefix("nfs"))
+            .or_else(|| lowercase.strip_prefix('v'))
+            .unwrap_or(&lowercase);
+        match version {
+            "3" => Ok(NfsVersion::NFSv3),
+            "4" => Ok(NfsVersion::NFSv4 { minor: None }),
+            "4.0" => Ok(NfsVersion::NFSv4 { minor: Some(0) }),
+            "4.1" => Ok(NfsVersion::NFSv4 { minor: Some(1) }),
+            "4.2" => Ok(NfsVersion::NFSv4 { minor: Some(2) }),
+            _ => Err(UnsupportedNfsVersion(input.to_string())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NfsVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct NfsVersionVisitor;
+
+        impl de::Visitor<'_> for NfsVersionVisitor {
+            type Value = NfsVersion;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an NFS version, such as 3 or \"v4\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                self.visit_str(&value.to_string())
+            }
+
+            // Unquoted minor versions (e.g. 4.1) are numbers in YAML and TOML
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value.parse().map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(NfsVersionVisitor)
+    }
 }
 
 /// The IP configuration for the target
@@ -29,6 +105,18 @@ pub enum TargetIpConfiguration {
     Static {},
 }
 
+/// Additional options for mounting the NFS root
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NfsOptions {
+    /// Mount options, appended to the `nfsroot=` parameter (e.g. `clientaddr=10.0.0.2`)
+    #[serde(default)]
+    pub mount: Vec<String>,
+    /// Standalone kernel command line parameters (e.g. `nfsrootdebug`)
+    #[serde(default)]
+    pub kernel: Vec<String>,
+}
+
 /// NFS Configuration for instant-netboot
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -44,58 +132,341 @@ pub struct NfsConfiguration {
     pub target_ip: TargetIpConfiguration,
     /// Whether the share should be mounted writable or not.
     pub is_writable: bool,
+    /// Additional mount options and kernel parameters
+    #[serde(default)]
+    pub options: NfsOptions,
+}
+
+impl NfsConfiguration {
+    /// The path of the share given to the client. NFSv4 servers resolve the path within their
+    /// pseudo-filesystem, so redundant separators and `.` components are removed.
+    pub fn export_path(&self) -> PathBuf {
+        match self.version {
+            NfsVersion::NFSv3 => self.share.clone(),
+            NfsVersion::NFSv4 { .. } => self.share.components().collect(),
+        }
+    }
+}
+
+/// The NFS shares mounted by each boot label
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NfsShares {
+    /// The share mounted by labels without a share of their own
+    pub default: Option<NfsConfiguration>,
+    /// Shares mounted by specific labels, by the name of the label
+    pub labels: BTreeMap<String, NfsConfiguration>,
+}
+
+impl NfsShares {
+    /// The share mounted by the label with this name, if any
+    pub fn for_label(&self, name: &str) -> Option<&NfsConfiguration> {
+        self.labels.get(name).or(self.default.as_ref())
+    }
+
+    /// Every share
+    pub fn iter(&self) -> impl Iterator<Item = &NfsConfiguration> {
+        self.default.iter().chain(self.labels.values())
+    }
+
+    /// Every share, mutably
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut NfsConfiguration> {
+        self.default.iter_mut().chain(self.labels.values_mut())
+    }
+}
+
+impl From<NfsConfiguration> for NfsShares {
+    fn from(value: NfsConfiguration) -> Self {
+        NfsShares {
+            default: Some(value),
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+/// An NFS share in a list of them, mounted by the label named `label`, or by every other label if
+/// `label` isn't given.
+#[derive(Deserialize)]
+struct LabelNfsConfiguration {
+    label: Option<String>,
+    #[serde(flatten)]
+    nfs: NfsConfiguration,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawNfsShares {
+    One(NfsConfiguration),
+    Many(Vec<LabelNfsConfiguration>),
+}
+
+impl TryFrom<RawNfsShares> for NfsShares {
+    type Error = String;
+
+    fn try_from(value: RawNfsShares) -> Result<Self, Self::Error> {
+        let shares = match value {
+            RawNfsShares::One(nfs) => return Ok(nfs.into()),
+            RawNfsShares::Many(shares) => shares,
+        };
+        let mut result = NfsShares::default();
+        for share in shares {
+            match share.label {
+                Some(label) if result.labels.contains_key(&label) => {
+                    return Err(format!("more than one NFS share for label {}", label))
+                }
+                Some(label) => {
+                    result.labels.insert(label, share.nfs);
+                }
+                None if result.default.is_some() => {
+                    return Err("more than one NFS share without a label".to_string())
+                }
+                None => result.default = Some(share.nfs),
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl<'de> Deserialize<'de> for NfsShares {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        RawNfsShares::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
 }
 
 /// This netboot server is a "just add water" solution for netbooting Linux machines in
 /// development.
-#[derive(Debug)]
 pub struct NetbootServer {
     // TODO: Make the type of boot loader entry configurable.
-    configuration: syslinux::Label,
-    nfs: Option<NfsConfiguration>,
+    resolver: Box<dyn ConfigResolver>,
+    nfs: NfsShares,
+    next_server: Option<IpAddr>,
+    root: Option<PathBuf>,
+    generated_tsize: bool,
+    decompress_gzip: bool,
+    append_directory: Option<PathBuf>,
+    config_prefixes: Vec<ConfigPrefix>,
+    counters: RequestCounters,
+}
+
+/// Counts of the requests handled by a server
+#[derive(Debug, Default)]
+struct RequestCounters {
+    pxe_config: AtomicU64,
+    boot_file: AtomicU64,
+    not_found: AtomicU64,
+}
+
+/// A snapshot of the requests handled by a server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of PXE configurations served
+    pub pxe_config: u64,
+    /// The number of boot files (or files from the root directory) served
+    pub boot_file: u64,
+    /// The number of requests for files that don't exist
+    pub not_found: u64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} PXE configuration(s), {} boot file(s), {} not found",
+            self.pxe_config, self.boot_file, self.not_found
+        )
+    }
+}
+
+impl fmt::Debug for NetbootServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NetbootServer")
+            .field("nfs", &self.nfs)
+            .field("next_server", &self.next_server)
+            .field("root", &self.root)
+            .field("counters", &self.counters)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An Ethernet hardware address
+pub type MacAddress = [u8; 6];
+
+/// The identity a PXE client uses to request its configuration, i.e. the name of the file it
+/// requests from `pxelinux.cfg/`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ClientId {
+    /// The client's UUID (e.g. from SMBIOS)
+    Uuid(uuid::Uuid),
+    /// The client's Ethernet hardware address
+    Mac(MacAddress),
+    /// The client's IPv4 address
+    Ip(Ipv4Addr),
+    /// Any other client. This is `pxelinux.cfg/default`, or a partial IP address, which clients
+    /// only request after the configuration for their full address was not found.
+    Default,
+}
+
+/// The name of the file in pxelinux.cfg requested by the client
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Lowercase and hyphenated, as PXELINUX requests it
+            ClientId::Uuid(uuid) => write!(f, "{}", uuid.hyphenated()),
+            ClientId::Mac(address) => {
+                write!(f, "01")?;
+                for octet in address {
+                    write!(f, "-{:02x}", octet)?;
+                }
+                Ok(())
+            }
+            ClientId::Ip(address) => write!(f, "{:08X}", u32::from(*address)),
+            ClientId::Default => write!(f, "default"),
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("request path is invalid")]
     InvalidRequestPath,
     #[error("no such file or directory")]
     FileNotFound,
+    #[error("access denied")]
+    AccessDenied,
     #[error("I/O error")]
-    IoError,
+    IoError(#[source] io::Error),
 }
 
-/// Returns Ok(true) if the path is for a PXE configuration file. Returns Err if the path is
-/// invalid.
-fn is_pxe_config_path(path: &Path) -> Result<bool, Error> {
-    let Ok(path) = path.strip_prefix(Path::new("pxelinux.cfg")) else {
-        return Ok(false);
-    };
-    let path = path.to_str().ok_or(Error::InvalidRequestPath)?;
+/// The format of a generated boot configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigFormat {
+    /// A PXELINUX configuration, requested by the name of the client (e.g.
+    /// `01-88-99-aa-bb-cc-dd`, `C0A80164` or `default`)
+    Syslinux,
+    /// A GRUB configuration, requested as `grub.cfg-` followed by the name of the client, or
+    /// `grub.cfg`
+    Grub,
+}
+
+/// A directory from which clients request their boot configuration. Each boot loader (and so each
+/// client architecture, e.g. PXELINUX for BIOS and GRUB for UEFI) has its own.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct ConfigPrefix {
+    /// The directory, relative to the TFTP root (e.g. `pxelinux.cfg`)
+    pub path: PathBuf,
+    /// The format of the configurations served from this directory
+    pub format: ConfigFormat,
+    /// The architecture of the clients requesting from this directory, as an EFI architecture
+    /// name (e.g. `x64`, `aa64`). These clients are only served boot entries with the same
+    /// `architecture` key, or none.
+    #[serde(default)]
+    pub architecture: Option<String>,
+}
+
+impl ConfigPrefix {
+    /// `pxelinux.cfg`, where PXELINUX requests its configuration
+    pub fn pxelinux() -> Self {
+        ConfigPrefix {
+            path: "pxelinux.cfg".into(),
+            format: ConfigFormat::Syslinux,
+            architecture: None,
+        }
+    }
+}
 
+/// Returns the identity of the client if the path is for a PXE configuration file. Returns Err if
+/// the path is invalid.
+pub fn pxe_client_id(path: &Path) -> Result<Option<ClientId>, Error> {
+    Ok(config_client_id(path, &[ConfigPrefix::pxelinux()])?.map(|(client, _)| client))
+}
+
+/// Returns the identity of the client and the prefix it requested from if the path is for a
+/// configuration file in one of `prefixes`. Returns Err if the path is invalid.
+pub(crate) fn config_client_id<'a>(
+    path: &Path,
+    prefixes: &'a [ConfigPrefix],
+) -> Result<Option<(ClientId, &'a ConfigPrefix)>, Error> {
+    let path = path.strip_prefix("/").unwrap_or(path);
+    for prefix in prefixes {
+        let Ok(name) = path.strip_prefix(&prefix.path) else {
+            continue;
+        };
+        let name = name.to_str().ok_or(Error::InvalidRequestPath)?;
+        let name = match prefix.format {
+            ConfigFormat::Syslinux => Some(name),
+            ConfigFormat::Grub => match name.strip_prefix("grub.cfg") {
+                Some("") => Some("default"),
+                Some(name) => name.strip_prefix('-'),
+                None => None,
+            },
+        };
+        if let Some(client) = name.map(client_id).transpose()?.flatten() {
+            return Ok(Some((client, prefix)));
+        }
+    }
+    Ok(None)
+}
+
+/// Parse the name of a client, as it appears in the name of its configuration file. Returns Err
+/// for a hardware address without the medium type, which no PXE client requests.
+fn client_id(path: &str) -> Result<Option<ClientId>, Error> {
     // An UUID
-    const UUID: LazyCell<Regex> = LazyCell::new(|| {
+    static UUID: LazyLock<Regex> = LazyLock::new(|| {
         Regex::new(r"^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap()
     });
     // A hyphen-separated MAC address prefixed by 01 (this is the medium type--01 is Ethernet)
-    const MAC_ADDRESS: LazyCell<Regex> =
-        LazyCell::new(|| Regex::new(r"^01-([0-9a-f]{2}-){5}[0-9a-f]{2}$").unwrap());
+    static MAC_ADDRESS: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^01-([0-9a-f]{2}-){5}[0-9a-f]{2}$").unwrap());
+    // A hyphen-separated MAC address without the medium type
+    static BARE_MAC_ADDRESS: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^([0-9a-fA-F]{2}-){5}[0-9a-fA-F]{2}$").unwrap());
     // An IP address encoded in hexadecimal
-    const IP_ADDRESS: LazyCell<Regex> = LazyCell::new(|| Regex::new(r"^[A-F0-9]{1,8}$").unwrap());
-    Ok(UUID.is_match(path) || MAC_ADDRESS.is_match(path) || IP_ADDRESS.is_match(path))
+    static IP_ADDRESS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[A-F0-9]{1,8}$").unwrap());
+
+    if UUID.is_match(path) {
+        let uuid = uuid::Uuid::parse_str(path).map_err(|_| Error::InvalidRequestPath)?;
+        Ok(Some(ClientId::Uuid(uuid)))
+    } else if MAC_ADDRESS.is_match(path) {
+        let mut address = [0; 6];
+        for (octet, digits) in address.iter_mut().zip(path.split('-').skip(1)) {
+            *octet = u8::from_str_radix(digits, 16).map_err(|_| Error::InvalidRequestPath)?;
+        }
+        Ok(Some(ClientId::Mac(address)))
+    } else if BARE_MAC_ADDRESS.is_match(path) {
+        Err(Error::InvalidRequestPath)
+    } else if IP_ADDRESS.is_match(path) && path.len() == 8 {
+        let address = u32::from_str_radix(path, 16).map_err(|_| Error::InvalidRequestPath)?;
+        Ok(Some(ClientId::Ip(address.into())))
+    } else if IP_ADDRESS.is_match(path) || path == "default" {
+        Ok(Some(ClientId::Default))
+    } else {
+        Ok(None)
+    }
 }
 
 fn make_nfsroot_option(nfs: &NfsConfiguration) -> String {
     let version = match nfs.version {
-        NfsVersion::NFSv3 => "3",
-        NfsVersion::NFSv4 => "4",
+        NfsVersion::NFSv3 => "3".to_string(),
+        NfsVersion::NFSv4 { minor: None } => "4".to_string(),
+        NfsVersion::NFSv4 { minor: Some(minor) } => format!("4.{}", minor),
     };
-    format!(
+    let mut option = format!(
         "nfsroot={}:{},vers={},tcp",
         nfs.host,
-        nfs.share.display(),
+        nfs.export_path().display(),
         version
-    )
+    );
+    for mount_option in &nfs.options.mount {
+        option.push(',');
+        option.push_str(mount_option);
+    }
+    option
 }
 
 fn make_ip_option(config: &TargetIpConfiguration) -> String {
@@ -111,7 +482,7 @@ fn make_ip_option(config: &TargetIpConfiguration) -> String {
 
 /// Update the configuration with NFS parameters
 fn make_nfs_configuration(
-    mut configuration: syslinux::Label,
+    configuration: syslinux::Label,
     nfs: &NfsConfiguration,
 ) -> syslinux::Label {
     let mut nfs_args = vec![
@@ -125,7 +496,29 @@ fn make_nfs_configuration(
         "rootwait".to_string(),
         make_ip_option(&nfs.target_ip),
     ];
+    nfs_args.extend(nfs.options.kernel.iter().cloned());
+    let mut configuration = append_kernel_arguments(configuration, nfs_args);
+
+    // With ip=dhcp, the kernel configures whichever interface answers first. BOOTIF= tells
+    // userspace (e.g. dracut) which interface the target actually booted from.
+    if matches!(nfs.target_ip, TargetIpConfiguration::Dhcp)
+        && !configuration
+            .directives
+            .iter()
+            .any(|directive| matches!(directive, syslinux::LabelDirective::IpAppend(_)))
+    {
+        configuration
+            .directives
+            .push(syslinux::LabelDirective::IpAppend(2));
+    }
+    configuration
+}
 
+/// Add arguments to the end of the kernel command line
+fn append_kernel_arguments(
+    mut configuration: syslinux::Label,
+    mut arguments: Vec<String>,
+) -> syslinux::Label {
     // Have to find the existing APPEND directive, if it exists
     if let Some(options) = configuration
         .directives
@@ -136,71 +529,1345 @@ fn make_nfs_configuration(
             // INVARIANT: We just sought the Append() directive.
             unreachable!()
         };
-        current_args.append(&mut nfs_args);
+        current_args.append(&mut arguments);
     }
     // Otherwise, add an APPEND directive
     else {
         configuration
             .directives
-            .push(syslinux::LabelDirective::Append(nfs_args));
+            .push(syslinux::LabelDirective::Append(arguments));
+    }
+    configuration
+}
+
+/// Substitute macros (such as `${next-server}`) in a single value
+fn expand_macros(value: &str, next_server: &IpAddr) -> String {
+    value.replace("${next-server}", &next_server.to_string())
+}
+
+/// Substitute macros in the paths and options of this boot entry.
+pub(crate) fn expand_label_macros(
+    mut configuration: syslinux::Label,
+    next_server: &IpAddr,
+) -> syslinux::Label {
+    let expand_path = |path: &mut PathBuf| {
+        if let Some(value) = path.to_str() {
+            *path = expand_macros(value, next_server).into();
+        }
+    };
+
+    match configuration.kernel {
+        syslinux::Kernel::Kernel(ref mut image) => expand_path(image),
+        syslinux::Kernel::LocalBoot(_) => {}
+    }
+    for directive in configuration.directives.iter_mut() {
+        match directive {
+            syslinux::LabelDirective::Initrd(path)
+            | syslinux::LabelDirective::Fdt(path)
+            | syslinux::LabelDirective::FdtDir(path) => expand_path(path),
+            syslinux::LabelDirective::FdtOverlays(paths) => paths.iter_mut().for_each(expand_path),
+            syslinux::LabelDirective::Append(options) => {
+                for option in options.iter_mut() {
+                    *option = expand_macros(option, next_server);
+                }
+            }
+            syslinux::LabelDirective::IpAppend(_) | syslinux::LabelDirective::MenuLabel(_) => {}
+        }
     }
     configuration
 }
 
+/// Resolve a requested path to a file within `root`. Returns Err if the path would refer to a file
+/// outside of `root`, even through a symbolic link.
+async fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf, Error> {
+    let mut resolved = root.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            // Requests for "/foo" and "./foo" are relative to the root
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return Err(Error::AccessDenied),
+        }
+    }
+
+    let not_found = |error: io::Error| match error.kind() {
+        io::ErrorKind::NotFound => Error::FileNotFound,
+        _ => Error::IoError(error),
+    };
+    let root = async_std::fs::canonicalize(root).await.map_err(not_found)?;
+    let resolved = async_std::fs::canonicalize(resolved)
+        .await
+        .map_err(not_found)?;
+    if !resolved.starts_with(&root) {
+        return Err(Error::AccessDenied);
+    }
+    Ok(resolved.into())
+}
+
+/// A file being served, which can be read from any offset (e.g. for an HTTP range request)
+pub trait FileReader: AsyncRead + AsyncSeek + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncSeek + Send + Unpin> FileReader for T {}
+
+/// A boot file being served, along with its size
+pub struct BootFileReader {
+    file: File,
+    size: u64,
+}
+
+impl BootFileReader {
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        Ok(BootFileReader { file, size })
+    }
+
+    /// The size of the file when it was opened, given to clients as the transfer size
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl AsyncRead for BootFileReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.file).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for BootFileReader {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        position: io::SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.file).poll_seek(cx, position)
+    }
+}
+
+type GzipDecoder = async_compression::futures::bufread::GzipDecoder<BufReader<File>>;
+
+/// A gzip-compressed boot file, decompressed as it is read. The decompressed size isn't known
+/// without decompressing the whole file, so seeking backwards starts again from the beginning of
+/// the file, and seeking relative to the end first reads to the end.
+pub struct GzipFileReader {
+    path: PathBuf,
+    decoder: GzipDecoder,
+    /// The offset in the decompressed file
+    position: u64,
+    /// The decompressed size, once the end of the file has been reached
+    size: Option<u64>,
+    /// The seek in progress, and the offset it resolved to
+    seeking: Option<(io::SeekFrom, u64)>,
+    /// The file being opened again, to seek backwards
+    reopening: Option<Pin<Box<dyn Future<Output = io::Result<GzipDecoder>> + Send>>>,
+}
+
+impl GzipFileReader {
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        Ok(GzipFileReader {
+            path: path.to_path_buf(),
+            decoder: Self::decoder(path).await?,
+            position: 0,
+            size: None,
+            seeking: None,
+            reopening: None,
+        })
+    }
+
+    async fn decoder(path: &Path) -> io::Result<GzipDecoder> {
+        let file = File::open(path).await?;
+        let mut decoder = GzipDecoder::new(BufReader::new(file));
+        // Like gzip -d, decompress every member of the file
+        decoder.multiple_members(true);
+        Ok(decoder)
+    }
+
+    /// Read and discard the decompressed file until `target`, or the end of the file
+    fn poll_skip(&mut self, cx: &mut Context<'_>, target: u64) -> Poll<io::Result<()>> {
+        let mut buffer = [0; 8192];
+        while self.position < target {
+            let length = (target - self.position).min(buffer.len() as u64) as usize;
+            if ready!(Pin::new(&mut *self).poll_read(cx, &mut buffer[..length]))? == 0 {
+                break;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for GzipFileReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let length = ready!(Pin::new(&mut self.decoder).poll_read(cx, buf))?;
+        self.position += length as u64;
+        if length == 0 && !buf.is_empty() {
+            self.size = Some(self.position);
+        }
+        Poll::Ready(Ok(length))
+    }
+}
+
+impl AsyncSeek for GzipFileReader {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        position: io::SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = &mut *self;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative offset");
+        loop {
+            if let Some(reopening) = &mut this.reopening {
+                this.decoder = ready!(reopening.as_mut().poll(cx))?;
+                this.reopening = None;
+                this.position = 0;
+            }
+            let target = match (this.seeking, position) {
+                // Seeking may take several polls, and the current position changes meanwhile
+                (Some((seeking, target)), _) if seeking == position => target,
+                (_, io::SeekFrom::Start(offset)) => offset,
+                (_, io::SeekFrom::Current(offset)) => this
+                    .position
+                    .checked_add_signed(offset)
+                    .ok_or_else(invalid)?,
+                (_, io::SeekFrom::End(offset)) => match this.size {
+                    Some(size) => size.checked_add_signed(offset).ok_or_else(invalid)?,
+                    None => {
+                        ready!(this.poll_skip(cx, u64::MAX))?;
+                        continue;
+                    }
+                },
+            };
+            this.seeking = Some((position, target));
+            if target < this.position {
+                let path = this.path.clone();
+                this.reopening = Some(Box::pin(async move { Self::decoder(&path).await }));
+                continue;
+            }
+            ready!(this.poll_skip(cx, target))?;
+            this.seeking = None;
+            return Poll::Ready(Ok(this.position));
+        }
+    }
+}
+
+/// The form of a path used to match requests to boot files: absolute, without `.` components or
+/// repeated separators. Clients differ in whether they request `Image`, `/Image` or `./Image`, and
+/// all of these refer to the boot file `/Image`. `..` components are kept, so such a path only
+/// matches a boot file listed with the same `..` components.
+pub(crate) fn canonical_path(path: &Path) -> PathBuf {
+    let mut canonical = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(_) | Component::ParentDir => canonical.push(component),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    canonical
+}
+
 /// Get the list of files mentioned in this boot entry.
-fn listed_files<'a>(label: &'a syslinux::Label) -> impl Iterator<Item = &'a Path> {
+pub(crate) fn listed_files(label: &syslinux::Label) -> impl Iterator<Item = &Path> {
     label
         .directives
         .iter()
-        .filter_map(|key| key.boot_file())
-        // TODO: Proper use of a "never pattern" in fn listed_files
-        .chain([label.kernel.boot_file().unwrap()])
+        // Directories are served by listed_directories instead
+        .filter(|key| !matches!(key, syslinux::LabelDirective::FdtDir(_)))
+        .flat_map(|key| key.boot_files())
+        // Some kernel-like directives (e.g. booting from a local disk) have no associated file
+        .chain(label.kernel.boot_files())
+}
+
+/// Get the directories mentioned in this boot entry, whose files are all served.
+pub(crate) fn listed_directories(label: &syslinux::Label) -> impl Iterator<Item = &Path> {
+    label
+        .directives
+        .iter()
+        .filter_map(|directive| match directive {
+            syslinux::LabelDirective::FdtDir(directory) => Some(directory.as_path()),
+            _ => None,
+        })
 }
 
 impl NetbootServer {
-    pub fn new(configuration: syslinux::Label) -> Self {
+    /// Serve the same configuration to every client.
+    #[cfg(test)]
+    pub fn new(configuration: syslinux::Configuration) -> Self {
+        Self::with_resolver(
+            Box::new(crate::resolver::MapResolver::new(configuration)),
+            NfsShares::default(),
+        )
+    }
+
+    /// Select the configuration for each client using a custom resolver.
+    pub fn with_resolver(resolver: Box<dyn ConfigResolver>, nfs: NfsShares) -> Self {
         Self {
-            configuration,
-            nfs: None,
+            resolver,
+            nfs,
+            next_server: None,
+            root: None,
+            generated_tsize: true,
+            decompress_gzip: false,
+            append_directory: None,
+            config_prefixes: vec![ConfigPrefix::pxelinux()],
+            counters: RequestCounters::default(),
         }
     }
 
-    pub fn with_nfs(configuration: syslinux::Label, nfs: NfsConfiguration) -> Self {
+    /// Serve files from this directory if they aren't a PXE configuration or a boot file.
+    pub fn with_root(self, root: PathBuf) -> Self {
         Self {
-            configuration,
-            nfs: Some(nfs),
+            root: Some(root),
+            ..self
+        }
+    }
+
+    /// Expand the `${next-server}` macro in the boot configuration to the given address. This is
+    /// the address PXE clients should use to reach this server.
+    pub fn with_next_server(self, next_server: IpAddr) -> Self {
+        Self {
+            next_server: Some(next_server),
+            ..self
         }
     }
 
+    /// Whether to tell clients the size of generated PXE configurations. Some clients misbehave
+    /// when the transfer size of dynamically generated content is given. The size of static files
+    /// is always given.
+    pub fn with_generated_tsize(self, generated_tsize: bool) -> Self {
+        Self {
+            generated_tsize,
+            ..self
+        }
+    }
+
+    /// Serve the decompressed contents of `<file>.gz` to clients requesting `<file>`, if `<file>`
+    /// isn't served itself. The decompressed size isn't known up front, so clients aren't told
+    /// the size of these files.
+    pub fn with_decompress_gzip(self, decompress_gzip: bool) -> Self {
+        Self {
+            decompress_gzip,
+            ..self
+        }
+    }
+
+    /// Append the contents of `<client>.append` in this directory to the kernel command line of
+    /// that client, where `<client>` is the name of the PXE configuration file it requested (e.g.
+    /// 01-aa-bb-cc-dd-ee-ff.append).
+    pub fn with_append_directory(self, directory: PathBuf) -> Self {
+        Self {
+            append_directory: Some(directory),
+            ..self
+        }
+    }
+
+    /// Serve configurations from these directories, instead of only from `pxelinux.cfg`.
+    pub fn with_config_prefixes(self, config_prefixes: Vec<ConfigPrefix>) -> Self {
+        Self {
+            config_prefixes,
+            ..self
+        }
+    }
+
+    /// Whether this path is for a configuration generated by this server
+    pub fn is_config_path(&self, path: &Path) -> bool {
+        matches!(config_client_id(path, &self.config_prefixes), Ok(Some(_)))
+    }
+
+    /// Expand macros in a label, if the values for them are known.
+    fn expand_macros(&self, label: &syslinux::Label) -> syslinux::Label {
+        match &self.next_server {
+            Some(next_server) => expand_label_macros(label.clone(), next_server),
+            None => label.clone(),
+        }
+    }
+
+    /// Generate the PXE configuration served to this client. Every path which serves a
+    /// configuration renders the result of this.
+    pub async fn render_config_for(
+        &self,
+        client: &ClientId,
+    ) -> Result<syslinux::Configuration, Error> {
+        self.render_config(client, None).await
+    }
+
+    /// Like [NetbootServer::render_config_for], for a client requesting its configuration from a
+    /// prefix for `architecture`.
+    async fn render_config(
+        &self,
+        client: &ClientId,
+        architecture: Option<&str>,
+    ) -> Result<syslinux::Configuration, Error> {
+        let configuration = match architecture {
+            Some(architecture) => self.resolver.resolve_for_architecture(client, architecture),
+            None => self.resolver.resolve(client),
+        };
+        let configuration = configuration.ok_or(Error::FileNotFound)?;
+        let mut labels = configuration
+            .labels
+            .iter()
+            .map(|label| self.expand_macros(label))
+            .collect::<Vec<_>>();
+        labels = labels
+            .into_iter()
+            .map(|label| match self.nfs.for_label(label.title()) {
+                Some(nfs) => make_nfs_configuration(label, nfs),
+                None => label,
+            })
+            .collect();
+        if let Some(directory) = &self.append_directory {
+            let path = directory.join(format!("{}.append", client));
+            match async_std::fs::read_to_string(path).await {
+                Ok(contents) => {
+                    let arguments = contents
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect::<Vec<_>>();
+                    labels = labels
+                        .into_iter()
+                        .map(|label| append_kernel_arguments(label, arguments.clone()))
+                        .collect();
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(Error::IoError(error)),
+            }
+        }
+        Ok(syslinux::Configuration {
+            default: configuration.default.clone(),
+            menu_title: configuration.menu_title.clone(),
+            labels,
+        })
+    }
+
     /// Route a TFTP GET request to this server. If the path refers to a PXE configuration, the
     /// configuration is generated. If it refers to a boot file, the file is served, etc.
     pub async fn tftp_get(
-        &mut self,
+        &self,
         path: &Path,
     ) -> Result<Box<dyn AsyncRead + Send + Unpin + 'static>, Error> {
+        Ok(self.tftp_open(path).await?.0)
+    }
+
+    /// Like [NetbootServer::tftp_get], but also returns the size of the file if it should be
+    /// given to the client.
+    pub async fn tftp_open(
+        &self,
+        path: &Path,
+    ) -> Result<(Box<dyn FileReader>, Option<u64>), Error> {
+        let result = self.open(path).await;
+        if let Err(Error::FileNotFound) = result {
+            self.counters.not_found.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// The requests handled by this server so far
+    pub fn stats(&self) -> Stats {
+        Stats {
+            pxe_config: self.counters.pxe_config.load(Ordering::Relaxed),
+            boot_file: self.counters.boot_file.load(Ordering::Relaxed),
+            not_found: self.counters.not_found.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn open(&self, path: &Path) -> Result<(Box<dyn FileReader>, Option<u64>), Error> {
         // If it's pxelinux.cfg/C0A802BA (or if it matches that pattern) generate a boot
         // configuration and return that.
-        if is_pxe_config_path(path)? {
-            let configuration = if let Some(nfs) = &self.nfs {
-                Cow::Owned(make_nfs_configuration(self.configuration.clone(), nfs))
-            } else {
-                Cow::Borrowed(&self.configuration)
+        if let Some((client, prefix)) = config_client_id(path, &self.config_prefixes)? {
+            tracing::Span::current().record("action", "pxe-config");
+            let configuration = self
+                .render_config(&client, prefix.architecture.as_deref())
+                .await?;
+            let configuration = match prefix.format {
+                ConfigFormat::Syslinux => configuration.to_string(),
+                ConfigFormat::Grub => grub::render(&configuration),
             };
-
-            return Ok(Box::new(futures::io::Cursor::new(
-                configuration.to_string(),
-            )));
+            let size = self.generated_tsize.then_some(configuration.len() as u64);
+            self.counters.pxe_config.fetch_add(1, Ordering::Relaxed);
+            return Ok((Box::new(futures::io::Cursor::new(configuration)), size));
         }
 
         // Otherwise, if it's a path to a file that we are serving (a boot file), serve it!
-        match listed_files(&self.configuration)
-            .find(|file| *file == path)
-            .ok_or(Error::FileNotFound)
-        {
-            Ok(file) => Ok(Box::new(
-                File::open(file).await.map_err(|_| Error::IoError)?,
-            )),
-            Err(_) => Err(Error::FileNotFound),
+        let file = match self.find_file(path).await {
+            Err(Error::FileNotFound) if self.decompress_gzip => {
+                let mut compressed = path.as_os_str().to_owned();
+                compressed.push(".gz");
+                let file = self.find_file(Path::new(&compressed)).await?;
+                tracing::Span::current().record("action", "boot-file");
+                let file = GzipFileReader::open(&file).await.map_err(Error::IoError)?;
+                self.counters.boot_file.fetch_add(1, Ordering::Relaxed);
+                return Ok((Box::new(file), None));
+            }
+            file => file?,
+        };
+        tracing::Span::current().record("action", "boot-file");
+        let file = BootFileReader::open(&file).await.map_err(Error::IoError)?;
+        let size = file.size();
+        self.counters.boot_file.fetch_add(1, Ordering::Relaxed);
+        Ok((Box::new(file), Some(size)))
+    }
+
+    /// Find the boot file, or the file in the root directory, at this path
+    async fn find_file(&self, path: &Path) -> Result<PathBuf, Error> {
+        let labels = self
+            .resolver
+            .labels()
+            .map(|label| self.expand_macros(label))
+            .collect::<Vec<_>>();
+        let canonical = canonical_path(path);
+        let directory = labels
+            .iter()
+            .flat_map(listed_directories)
+            .find_map(|directory| {
+                canonical
+                    .strip_prefix(canonical_path(directory))
+                    .ok()
+                    .filter(|file| !file.as_os_str().is_empty())
+                    .map(|file| (directory, file.to_path_buf()))
+            });
+        let file = labels
+            .iter()
+            .flat_map(listed_files)
+            .find(|file| canonical_path(file) == canonical)
+            .map(Path::to_path_buf);
+        match file {
+            Some(file) => Ok(file),
+            // Files within a device tree directory (FDTDIR) are chosen by the client.
+            None => match directory {
+                Some((directory, file)) => resolve_in_root(directory, &file).await,
+                // Finally, try to find it in the root directory.
+                None => match &self.root {
+                    Some(root) => resolve_in_root(root, path).await,
+                    None => Err(Error::FileNotFound),
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolver::MapResolver;
+    use async_std::task::block_on;
+    use futures::AsyncReadExt;
+
+    fn read_to_string(mut reader: impl AsyncRead + Unpin) -> String {
+        let mut contents = String::new();
+        block_on(reader.read_to_string(&mut contents)).unwrap();
+        contents
+    }
+
+    #[test]
+    fn nfs_version_from_str() {
+        for input in ["3", "v3", "nfs3", "NFSv3"] {
+            assert_eq!(input.parse(), Ok(NfsVersion::NFSv3));
+        }
+        for input in ["4", "V4", "nfs4", "nfsv4"] {
+            assert_eq!(input.parse(), Ok(NfsVersion::NFSv4 { minor: None }));
+        }
+        for input in ["4.1", "v4.1", "nfs4.1", "NFSv4.1"] {
+            assert_eq!(input.parse(), Ok(NfsVersion::NFSv4 { minor: Some(1) }));
+        }
+    }
+
+    #[test]
+    fn nfs_version_from_str_rejected() {
+        for input in ["", "2", "v", "nfs", "3.1", "4.3", "4.", "nfsv 4", "v3 "] {
+            assert_eq!(
+                input.parse::<NfsVersion>(),
+                Err(UnsupportedNfsVersion(input.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn nfs_version_deserialize() {
+        let versions: Vec<NfsVersion> =
+            serde_yaml::from_str("[3, \"v3\", nfs4, 4, 4.2, \"4.0\"]").unwrap();
+        assert_eq!(
+            versions,
+            vec![
+                NfsVersion::NFSv3,
+                NfsVersion::NFSv3,
+                NfsVersion::NFSv4 { minor: None },
+                NfsVersion::NFSv4 { minor: None },
+                NfsVersion::NFSv4 { minor: Some(2) },
+                NfsVersion::NFSv4 { minor: Some(0) },
+            ]
+        );
+        assert!(serde_yaml::from_str::<NfsVersion>("2").is_err());
+    }
+
+    fn nfs_configuration() -> NfsConfiguration {
+        NfsConfiguration {
+            host: "192.168.1.1".parse().unwrap(),
+            share: "/srv/nfs".into(),
+            version: NfsVersion::NFSv4 { minor: None },
+            target_ip: TargetIpConfiguration::Dhcp,
+            is_writable: false,
+            options: NfsOptions::default(),
+        }
+    }
+
+    fn label() -> syslinux::Label {
+        syslinux::Label {
+            name: "test".to_string(),
+            kernel: syslinux::Kernel::Kernel("/Image".into()),
+            directives: vec![],
+        }
+    }
+
+    /// A server for [label], booting `kernel`
+    fn server(kernel: &Path) -> NetbootServer {
+        NetbootServer::new(
+            syslinux::Label {
+                kernel: syslinux::Kernel::Kernel(kernel.into()),
+                ..label()
+            }
+            .into(),
+        )
+    }
+
+    #[test]
+    fn nfs_options() {
+        let nfs = NfsConfiguration {
+            options: NfsOptions {
+                mount: vec![
+                    "clientaddr=192.168.1.2".to_string(),
+                    "migration".to_string(),
+                ],
+                kernel: vec!["nfsrootdebug".to_string()],
+            },
+            ..nfs_configuration()
+        };
+        let configuration = make_nfs_configuration(label(), &nfs);
+        assert_eq!(
+            configuration.directives,
+            vec![
+                syslinux::LabelDirective::Append(
+                    [
+                        "root=/dev/nfs",
+                        "ro",
+                        "nfsroot=192.168.1.1:/srv/nfs,vers=4,tcp,clientaddr=192.168.1.2,migration",
+                        "rootwait",
+                        "ip=dhcp",
+                        "nfsrootdebug",
+                    ]
+                    .into_iter()
+                    .map(|s| s.to_string())
+                    .collect()
+                ),
+                syslinux::LabelDirective::IpAppend(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn nfs_dhcp_ip_append() {
+        let configuration = make_nfs_configuration(label(), &nfs_configuration());
+        assert_eq!(
+            configuration.to_string(),
+            "LABEL test\nKERNEL /Image\nAPPEND root=/dev/nfs ro nfsroot=192.168.1.1:/srv/nfs,vers=4,tcp rootwait ip=dhcp\nIPAPPEND 2\n"
+        );
+
+        // An IPAPPEND directive chosen by the user is kept, and not duplicated.
+        let label = syslinux::Label {
+            directives: vec![syslinux::LabelDirective::IpAppend(1)],
+            ..label()
+        };
+        let configuration = make_nfs_configuration(label, &nfs_configuration());
+        let ip_appends = configuration
+            .directives
+            .iter()
+            .filter(|directive| matches!(directive, syslinux::LabelDirective::IpAppend(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(ip_appends, vec![&syslinux::LabelDirective::IpAppend(1)]);
+    }
+
+    #[test]
+    fn nfs_v4_export_path() {
+        let nfs = NfsConfiguration {
+            share: "/srv//nfs/./rootfs/".into(),
+            ..nfs_configuration()
+        };
+        assert_eq!(nfs.export_path(), Path::new("/srv/nfs/rootfs"));
+        assert_eq!(
+            make_nfsroot_option(&nfs),
+            "nfsroot=192.168.1.1:/srv/nfs/rootfs,vers=4,tcp"
+        );
+        let nfs = NfsConfiguration {
+            version: NfsVersion::NFSv3,
+            ..nfs
+        };
+        assert_eq!(nfs.export_path(), Path::new("/srv//nfs/./rootfs/"));
+    }
+
+    #[test]
+    fn nfsroot_version() {
+        let nfsroot = |version| {
+            make_nfsroot_option(&NfsConfiguration {
+                version,
+                ..nfs_configuration()
+            })
+        };
+        assert_eq!(
+            nfsroot(NfsVersion::NFSv3),
+            "nfsroot=192.168.1.1:/srv/nfs,vers=3,tcp"
+        );
+        assert_eq!(
+            nfsroot(NfsVersion::NFSv4 { minor: None }),
+            "nfsroot=192.168.1.1:/srv/nfs,vers=4,tcp"
+        );
+        assert_eq!(
+            nfsroot(NfsVersion::NFSv4 { minor: Some(0) }),
+            "nfsroot=192.168.1.1:/srv/nfs,vers=4.0,tcp"
+        );
+        assert_eq!(
+            nfsroot(NfsVersion::NFSv4 { minor: Some(2) }),
+            "nfsroot=192.168.1.1:/srv/nfs,vers=4.2,tcp"
+        );
+    }
+
+    #[test]
+    fn client_id_from_path() {
+        let client_id = |path: &str| pxe_client_id(Path::new(path)).unwrap();
+        assert_eq!(
+            client_id("pxelinux.cfg/b8945908-d6a6-41a9-611d-74a6ab80b83d"),
+            Some(ClientId::Uuid(uuid::Uuid::from_u128(
+                0xb8945908_d6a6_41a9_611d_74a6ab80b83d
+            )))
+        );
+        assert_eq!(
+            client_id("pxelinux.cfg/01-88-99-aa-bb-cc-dd"),
+            Some(ClientId::Mac([0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd]))
+        );
+        assert_eq!(
+            client_id("pxelinux.cfg/C0A80101"),
+            Some(ClientId::Ip(Ipv4Addr::new(192, 168, 1, 1)))
+        );
+        assert_eq!(client_id("pxelinux.cfg/C0A8"), Some(ClientId::Default));
+        assert_eq!(client_id("pxelinux.cfg/default"), Some(ClientId::Default));
+        assert_eq!(client_id("pxelinux.cfg/foo"), None);
+        assert_eq!(client_id("Image"), None);
+        assert_eq!(
+            client_id("grub/grub.cfg-01-88-99-aa-bb-cc-dd"),
+            None,
+            "not a PXELINUX path"
+        );
+    }
+
+    #[test]
+    fn client_id_without_medium_type() {
+        for name in ["88-99-aa-bb-cc-dd", "88-99-AA-BB-CC-DD"] {
+            let result = pxe_client_id(&Path::new("pxelinux.cfg").join(name));
+            assert!(matches!(result, Err(Error::InvalidRequestPath)), "{}", name);
+        }
+        let server = NetbootServer::new(label().into());
+        let result = block_on(server.tftp_get(Path::new("pxelinux.cfg/88-99-aa-bb-cc-dd")));
+        assert!(matches!(result, Err(Error::InvalidRequestPath)));
+    }
+
+    /// Only serves a configuration to a single client
+    struct SingleClientResolver {
+        client: ClientId,
+        configuration: syslinux::Configuration,
+    }
+
+    impl ConfigResolver for SingleClientResolver {
+        fn resolve(&self, client: &ClientId) -> Option<&syslinux::Configuration> {
+            (*client == self.client).then_some(&self.configuration)
+        }
+
+        fn labels(&self) -> Box<dyn Iterator<Item = &syslinux::Label> + '_> {
+            Box::new(self.configuration.labels.iter())
+        }
+    }
+
+    #[test]
+    fn custom_resolver() {
+        let resolver = SingleClientResolver {
+            client: ClientId::Mac([0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd]),
+            configuration: label().into(),
+        };
+        let server = NetbootServer::with_resolver(Box::new(resolver), NfsShares::default());
+
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/01-88-99-aa-bb-cc-dd")));
+        assert_eq!(
+            read_to_string(reader.unwrap()),
+            "LABEL test\nKERNEL /Image\n"
+        );
+        let result = block_on(server.tftp_get(Path::new("pxelinux.cfg/default")));
+        assert!(matches!(result, Err(Error::FileNotFound)));
+    }
+
+    #[test]
+    fn map_resolver() {
+        let mut resolver = MapResolver::new(label().into());
+        resolver.insert(
+            ClientId::Ip(Ipv4Addr::new(192, 168, 1, 100)),
+            syslinux::Label {
+                name: "rescue".to_string(),
+                ..label()
+            }
+            .into(),
+        );
+        let server = NetbootServer::with_resolver(Box::new(resolver), NfsShares::default());
+
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/C0A80164")));
+        assert_eq!(
+            read_to_string(reader.unwrap()),
+            "LABEL rescue\nKERNEL /Image\n"
+        );
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/default")));
+        assert_eq!(
+            read_to_string(reader.unwrap()),
+            "LABEL test\nKERNEL /Image\n"
+        );
+    }
+
+    #[test]
+    fn serve_menu_title() {
+        let server = NetbootServer::new(syslinux::Configuration {
+            default: Some("test".to_string()),
+            menu_title: Some("Netboot".to_string()),
+            labels: vec![label()],
+        });
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/default")));
+        assert_eq!(
+            read_to_string(reader.unwrap()),
+            "DEFAULT test\nMENU TITLE Netboot\nLABEL test\nKERNEL /Image\n"
+        );
+    }
+
+    #[test]
+    fn serve_from_root() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("efi")).unwrap();
+        std::fs::write(root.path().join("efi/grubx64.efi"), "grub").unwrap();
+        let server = NetbootServer::new(label().into()).with_root(root.path().into());
+
+        for path in ["efi/grubx64.efi", "/efi/grubx64.efi", "./efi/grubx64.efi"] {
+            let reader = block_on(server.tftp_get(Path::new(path))).unwrap();
+            assert_eq!(read_to_string(reader), "grub");
+        }
+        let result = block_on(server.tftp_get(Path::new("efi/shimx64.efi")));
+        assert!(matches!(result, Err(Error::FileNotFound)));
+    }
+
+    /// Counts the bytes handed out by a reader, and the most handed out by a single read
+    struct CountingReader<R> {
+        reader: R,
+        total: u64,
+        largest: usize,
+        reads: usize,
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let result = Pin::new(&mut self.reader).poll_read(cx, buf);
+            if let Poll::Ready(Ok(length)) = result {
+                self.total += length as u64;
+                self.largest = self.largest.max(length);
+                self.reads += 1;
+            }
+            result
+        }
+    }
+
+    #[test]
+    fn stream_large_boot_file() {
+        const SIZE: u64 = 128 * 1024 * 1024;
+        let directory = tempfile::tempdir().unwrap();
+        let initrd = directory.path().join("initrd.img");
+        // Sparse, so that the test doesn't need the disk space either
+        std::fs::File::create(&initrd)
+            .unwrap()
+            .set_len(SIZE)
+            .unwrap();
+        let server = NetbootServer::new(
+            syslinux::Label {
+                directives: vec![syslinux::LabelDirective::Initrd(initrd.clone())],
+                ..label()
+            }
+            .into(),
+        );
+
+        let (reader, size) = block_on(server.tftp_open(&initrd)).unwrap();
+        assert_eq!(size, Some(SIZE));
+        let mut reader = CountingReader {
+            reader,
+            total: 0,
+            largest: 0,
+            reads: 0,
+        };
+        let mut buffer = vec![0; 64 * 1024];
+        while block_on(reader.read(&mut buffer)).unwrap() != 0 {}
+        assert_eq!(reader.total, SIZE);
+        // The file is handed out a buffer at a time as it is sent, rather than all at once
+        assert!(reader.largest <= buffer.len(), "{}", reader.largest);
+        assert!(reader.reads as u64 > SIZE / buffer.len() as u64);
+    }
+
+    /// Compress `contents` with gzip
+    fn gzip(contents: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut encoder = async_compression::futures::bufread::GzipEncoder::new(contents);
+        block_on(encoder.read_to_end(&mut compressed)).unwrap();
+        compressed
+    }
+
+    #[test]
+    fn serve_decompressed_gzip() {
+        use futures::AsyncSeekExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image.gz");
+        std::fs::write(&kernel, gzip(b"uncompressed kernel")).unwrap();
+        let uncompressed = directory.path().join("Image");
+        let server = server(&kernel);
+        let result = block_on(server.tftp_get(&uncompressed));
+        assert!(matches!(result, Err(Error::FileNotFound)));
+
+        let server = server.with_decompress_gzip(true);
+        let (reader, size) = block_on(server.tftp_open(&uncompressed)).unwrap();
+        // The decompressed size isn't known until the whole file is read
+        assert_eq!(size, None);
+        assert_eq!(read_to_string(reader), "uncompressed kernel");
+        // The compressed file is still served as it is
+        let (reader, size) = block_on(server.tftp_open(&kernel)).unwrap();
+        assert_eq!(size, std::fs::metadata(&kernel).ok().map(|file| file.len()));
+        drop(reader);
+
+        // HTTP finds the size by seeking to the end, then seeks back for a range
+        let (mut reader, _) = block_on(server.tftp_open(&uncompressed)).unwrap();
+        assert_eq!(block_on(reader.seek(io::SeekFrom::End(0))).unwrap(), 19);
+        assert_eq!(block_on(reader.seek(io::SeekFrom::Start(13))).unwrap(), 13);
+        assert_eq!(read_to_string(&mut reader), "kernel");
+        assert_eq!(
+            block_on(reader.seek(io::SeekFrom::Current(-6))).unwrap(),
+            13
+        );
+        assert_eq!(read_to_string(reader), "kernel");
+    }
+
+    #[test]
+    fn serve_from_root_rejects_traversal() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().join("tftp");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(directory.path().join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink("../secret", root.join("link")).unwrap();
+        let server = NetbootServer::new(label().into()).with_root(root);
+
+        for path in ["../secret", "efi/../../secret", "link"] {
+            let result = block_on(server.tftp_get(Path::new(path)));
+            assert!(matches!(result, Err(Error::AccessDenied)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn config_prefixes() {
+        let server = NetbootServer::new(label().into()).with_config_prefixes(vec![
+            ConfigPrefix::pxelinux(),
+            ConfigPrefix {
+                path: "grub".into(),
+                format: ConfigFormat::Grub,
+                architecture: None,
+            },
+        ]);
+
+        let syslinux = "LABEL test\nKERNEL /Image\n";
+        let grub = "menuentry 'test' {\n\tlinux '/Image'\n}\n";
+        for (path, expected) in [
+            ("pxelinux.cfg/01-88-99-aa-bb-cc-dd", syslinux),
+            ("/pxelinux.cfg/default", syslinux),
+            ("grub/grub.cfg-01-88-99-aa-bb-cc-dd", grub),
+            ("grub/grub.cfg-C0A80164", grub),
+            ("grub/grub.cfg", grub),
+        ] {
+            let reader = block_on(server.tftp_get(Path::new(path))).unwrap();
+            assert_eq!(read_to_string(reader), expected, "{}", path);
+            assert!(server.is_config_path(Path::new(path)));
+        }
+        for path in [
+            "grub/01-88-99-aa-bb-cc-dd",
+            "grub/grub.cfgx",
+            "pxelinux.cfg/grub.cfg",
+        ] {
+            assert!(!server.is_config_path(Path::new(path)), "{}", path);
+        }
+        assert_eq!(
+            config_client_id(
+                Path::new("grub/grub.cfg-01-88-99-aa-bb-cc-dd"),
+                &server.config_prefixes
+            )
+            .unwrap()
+            .map(|(client, prefix)| (client, prefix.format)),
+            Some((
+                ClientId::Mac([0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd]),
+                ConfigFormat::Grub
+            ))
+        );
+    }
+
+    #[test]
+    fn canonical_path() {
+        for path in ["Image", "/Image", "./Image", "//Image", "/./Image"] {
+            assert_eq!(super::canonical_path(Path::new(path)), Path::new("/Image"));
+        }
+        assert_eq!(
+            super::canonical_path(Path::new("boot//./efi/../Image")),
+            Path::new("/boot/efi/../Image")
+        );
+    }
+
+    #[test]
+    fn serve_boot_file_by_canonical_path() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        std::fs::write(&kernel, "kernel").unwrap();
+        let server = server(&kernel);
+
+        let relative = kernel.strip_prefix("/").unwrap();
+        for path in [
+            kernel.clone(),
+            relative.to_path_buf(),
+            Path::new(".").join(relative),
+            PathBuf::from(format!("/{}", kernel.display())),
+        ] {
+            let reader = block_on(server.tftp_get(&path)).unwrap();
+            assert_eq!(read_to_string(reader), "kernel", "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn serve_from_fdt_dir() {
+        let directory = tempfile::tempdir().unwrap();
+        let dtbs = directory.path().join("dtbs");
+        std::fs::create_dir_all(dtbs.join("rockchip")).unwrap();
+        std::fs::write(dtbs.join("rockchip/rk3399-rock-pi-4b.dtb"), "dtb").unwrap();
+        std::fs::write(directory.path().join("secret"), "secret").unwrap();
+        let server = NetbootServer::new(
+            syslinux::Label {
+                directives: vec![syslinux::LabelDirective::FdtDir(dtbs.clone())],
+                ..label()
+            }
+            .into(),
+        );
+
+        let reader =
+            block_on(server.tftp_get(&dtbs.join("rockchip/rk3399-rock-pi-4b.dtb"))).unwrap();
+        assert_eq!(read_to_string(reader), "dtb");
+        let result = block_on(server.tftp_get(&dtbs.join("../secret")));
+        assert!(matches!(result, Err(Error::AccessDenied)));
+        let result = block_on(server.tftp_get(&dtbs));
+        assert!(matches!(result, Err(Error::FileNotFound)));
+    }
+
+    #[test]
+    fn permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        std::fs::write(&kernel, "").unwrap();
+        std::fs::set_permissions(&kernel, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::File::open(&kernel).is_ok() {
+            // Permission checks are bypassed (e.g. we are running as root)
+            return;
+        }
+
+        let server = server(&kernel);
+        match block_on(server.tftp_get(&kernel)) {
+            Err(Error::IoError(error)) => {
+                assert_eq!(error.kind(), io::ErrorKind::PermissionDenied)
+            }
+            _ => panic!("expected a permission error"),
         }
     }
+
+    #[test]
+    fn generated_tsize() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("pxelinux.0"), "pxelinux").unwrap();
+        let size = |server: &NetbootServer, path: &str| {
+            block_on(server.tftp_open(Path::new(path))).unwrap().1
+        };
+
+        let server = NetbootServer::new(label().into()).with_root(root.path().into());
+        assert_eq!(size(&server, "pxelinux.cfg/default"), Some(25));
+        assert_eq!(size(&server, "pxelinux.0"), Some(8));
+
+        let server = server.with_generated_tsize(false);
+        assert_eq!(size(&server, "pxelinux.cfg/default"), None);
+        assert_eq!(size(&server, "pxelinux.0"), Some(8));
+    }
+
+    #[test]
+    fn render_config_for_nfs_client() {
+        let label = syslinux::Label {
+            directives: vec![syslinux::LabelDirective::Append(vec!["quiet".to_string()])],
+            ..label()
+        };
+        let server = NetbootServer::with_resolver(
+            Box::new(MapResolver::new(label.clone().into())),
+            nfs_configuration().into(),
+        );
+
+        let client = ClientId::Mac([0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd]);
+        let configuration = block_on(server.render_config_for(&client)).unwrap();
+        assert_eq!(
+            configuration,
+            make_nfs_configuration(label, &nfs_configuration()).into()
+        );
+    }
+
+    #[test]
+    fn nfs_share_per_label() {
+        let configuration = syslinux::Configuration {
+            default: None,
+            menu_title: None,
+            labels: vec![
+                label(),
+                syslinux::Label {
+                    name: "rescue".to_string(),
+                    ..label()
+                },
+            ],
+        };
+        let nfs = NfsShares {
+            default: Some(nfs_configuration()),
+            labels: [(
+                "rescue".to_string(),
+                NfsConfiguration {
+                    share: "/srv/rescue".into(),
+                    ..nfs_configuration()
+                },
+            )]
+            .into(),
+        };
+        let server = NetbootServer::with_resolver(Box::new(MapResolver::new(configuration)), nfs);
+
+        let configuration = block_on(server.render_config_for(&ClientId::Default)).unwrap();
+        let nfsroot = |label: &syslinux::Label| {
+            label
+                .directives
+                .iter()
+                .find_map(|directive| match directive {
+                    syslinux::LabelDirective::Append(options) => options
+                        .iter()
+                        .find(|option| option.starts_with("nfsroot="))
+                        .cloned(),
+                    _ => None,
+                })
+        };
+        assert_eq!(
+            configuration.labels.iter().map(nfsroot).collect::<Vec<_>>(),
+            vec![
+                Some("nfsroot=192.168.1.1:/srv/nfs,vers=4,tcp".to_string()),
+                Some("nfsroot=192.168.1.1:/srv/rescue,vers=4,tcp".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn boot_file_reader_seek() {
+        use futures::AsyncSeekExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("Image");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        block_on(async {
+            let mut reader = BootFileReader::open(&path).await.unwrap();
+            assert_eq!(reader.size(), 10);
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents).await.unwrap();
+            assert_eq!(contents, "0123456789");
+
+            reader.seek(io::SeekFrom::Start(4)).await.unwrap();
+            let mut block = [0; 3];
+            reader.read_exact(&mut block).await.unwrap();
+            assert_eq!(&block, b"456");
+        });
+    }
+
+    #[test]
+    fn stats() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("pxelinux.0"), "pxelinux").unwrap();
+        let server = NetbootServer::with_resolver(
+            Box::new(SingleClientResolver {
+                client: ClientId::Mac([0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd]),
+                configuration: label().into(),
+            }),
+            NfsShares::default(),
+        )
+        .with_root(root.path().into());
+
+        for path in [
+            "pxelinux.0",
+            "pxelinux.cfg/01-88-99-aa-bb-cc-dd",
+            "pxelinux.cfg/01-88-99-aa-bb-cc-dd",
+            "pxelinux.cfg/default",
+            "ldlinux.c32",
+            "../secret",
+        ] {
+            let _ = block_on(server.tftp_get(Path::new(path)));
+        }
+        assert_eq!(
+            server.stats(),
+            Stats {
+                pxe_config: 2,
+                boot_file: 1,
+                not_found: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn client_id_display() {
+        for path in [
+            "b8945908-d6a6-41a9-611d-74a6ab80b83d",
+            "01-88-99-aa-bb-cc-dd",
+            "C0A80101",
+            "default",
+        ] {
+            let client = pxe_client_id(&Path::new("pxelinux.cfg").join(path))
+                .unwrap()
+                .unwrap();
+            assert_eq!(client.to_string(), path);
+        }
+    }
+
+    #[test]
+    fn append_sidecar() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(
+            directory.path().join("01-88-99-aa-bb-cc-dd.append"),
+            "debug\nloglevel=7\n",
+        )
+        .unwrap();
+        let server = NetbootServer::new(
+            syslinux::Label {
+                directives: vec![syslinux::LabelDirective::Append(vec!["quiet".to_string()])],
+                ..label()
+            }
+            .into(),
+        )
+        .with_append_directory(directory.path().into());
+
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/01-88-99-aa-bb-cc-dd")));
+        assert_eq!(
+            read_to_string(reader.unwrap()),
+            "LABEL test\nKERNEL /Image\nAPPEND quiet debug loglevel=7\n"
+        );
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/01-00-11-22-33-44-55")));
+        assert_eq!(
+            read_to_string(reader.unwrap()),
+            "LABEL test\nKERNEL /Image\nAPPEND quiet\n"
+        );
+    }
+
+    #[test]
+    fn listed_files_includes_directives() {
+        let label = syslinux::Label {
+            directives: vec![
+                syslinux::LabelDirective::Initrd("/initrd.img".into()),
+                syslinux::LabelDirective::Append(vec!["quiet".to_string()]),
+                syslinux::LabelDirective::Fdt("/boot.dtb".into()),
+                syslinux::LabelDirective::FdtOverlays(vec!["/a.dtbo".into(), "/b.dtbo".into()]),
+            ],
+            ..label()
+        };
+        assert_eq!(
+            listed_files(&label).collect::<Vec<_>>(),
+            vec![
+                Path::new("/initrd.img"),
+                Path::new("/boot.dtb"),
+                Path::new("/a.dtbo"),
+                Path::new("/b.dtbo"),
+                Path::new("/Image")
+            ]
+        );
+    }
+
+    #[test]
+    fn local_boot_label() {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        std::fs::write(&kernel, "kernel").unwrap();
+        let local = syslinux::Label {
+            name: "local".to_string(),
+            kernel: syslinux::Kernel::LocalBoot(0),
+            directives: vec![],
+        };
+        assert_eq!(listed_files(&local).count(), 0);
+        let server = NetbootServer::new(syslinux::Configuration {
+            default: None,
+            menu_title: None,
+            labels: vec![
+                local,
+                syslinux::Label {
+                    kernel: syslinux::Kernel::Kernel(kernel.clone()),
+                    ..label()
+                },
+            ],
+        });
+
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/default"))).unwrap();
+        assert_eq!(
+            read_to_string(reader),
+            format!(
+                "LABEL local\nLOCALBOOT 0\nLABEL test\nKERNEL {}\n",
+                kernel.display()
+            )
+        );
+        let reader = block_on(server.tftp_get(&kernel)).unwrap();
+        assert_eq!(read_to_string(reader), "kernel");
+    }
+
+    #[test]
+    fn listed_files_after_rebase() {
+        let mut label = syslinux::Label {
+            kernel: syslinux::Kernel::Kernel("/boot/Image".into()),
+            directives: vec![syslinux::LabelDirective::Initrd("/boot/initrd.img".into())],
+            ..label()
+        };
+        label.rebase_boot_files(Path::new("/boot"), Path::new("/tftp"));
+        assert_eq!(
+            listed_files(&label).collect::<Vec<_>>(),
+            vec![Path::new("/tftp/initrd.img"), Path::new("/tftp/Image")]
+        );
+    }
+
+    #[test]
+    fn next_server_in_kernel_path() {
+        let configuration = syslinux::Label {
+            kernel: syslinux::Kernel::Kernel("http://${next-server}/Image".into()),
+            directives: vec![syslinux::LabelDirective::Append(vec![
+                "server=${next-server}".to_string(),
+            ])],
+            ..label()
+        };
+        let server = NetbootServer::new(configuration.into())
+            .with_next_server("192.168.1.10".parse().unwrap());
+
+        let reader = block_on(server.tftp_get(Path::new("pxelinux.cfg/C0A80164"))).unwrap();
+        assert_eq!(
+            read_to_string(reader),
+            "LABEL test\nKERNEL http://192.168.1.10/Image\nAPPEND server=192.168.1.10\n"
+        );
+    }
 }
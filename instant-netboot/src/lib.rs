@@ -0,0 +1,35 @@
+//! A "just add water" netboot server for Linux machines in development. The `instant-netboot`
+//! binary serves a [NetbootServer] over TFTP (and optionally HTTP), configured from a file, but
+//! the server can also be driven directly:
+//!
+//! ```
+//! use boot_loader_entries::syslinux;
+//! use instant_netboot::{resolver::MapResolver, ClientId, NetbootServer, NfsShares};
+//!
+//! let label = syslinux::Label {
+//!     name: "Linux".to_string(),
+//!     kernel: syslinux::Kernel::Kernel("/Image".into()),
+//!     directives: vec![],
+//! };
+//! let resolver = MapResolver::new(label.into());
+//! let server = NetbootServer::with_resolver(Box::new(resolver), NfsShares::default());
+//! let configuration =
+//!     async_std::task::block_on(server.render_config_for(&ClientId::Default)).unwrap();
+//! assert_eq!(configuration.to_string(), "LABEL Linux\nKERNEL /Image\n");
+//! ```
+
+pub mod config;
+mod grub;
+pub mod http;
+mod instant_netboot;
+pub mod proxy_dhcp;
+pub mod resolver;
+pub mod shutdown;
+pub mod socket_activation;
+pub mod tftp;
+
+pub use instant_netboot::{
+    pxe_client_id, BootFileReader, ClientId, ConfigFormat, ConfigPrefix, Error, FileReader,
+    GzipFileReader, MacAddress, NetbootServer, NfsConfiguration, NfsOptions, NfsShares, NfsVersion,
+    Stats, TargetIpConfiguration, UnsupportedNfsVersion,
+};
@@ -1,50 +1,680 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    io::Write,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use anyhow::anyhow;
 use async_std::task::block_on;
 use async_tftp::server::TftpServerBuilder;
 use clap::Parser;
-use instant_netboot::NetbootServer;
+use futures::{AsyncReadExt, FutureExt};
+use instant_netboot::{
+    config, http, proxy_dhcp, shutdown, socket_activation, tftp, ClientId, NetbootServer,
+};
 use tracing::info;
-
-mod config;
-mod instant_netboot;
-mod tftp;
+use tracing_subscriber::fmt::{format::FmtSpan, MakeWriter};
 
 #[derive(clap::Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// The configuration file
-    pub configuration: PathBuf,
+    /// The configuration file. Parsed as TOML if it has a .toml extension, YAML otherwise.
+    #[arg(required = true)]
+    pub configuration: Option<PathBuf>,
 
     /// Verbose logging
     #[arg(short, long, default_value_t = false)]
     pub verbose: bool,
+
+    /// The format of log messages
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Listen on this address (ADDR:PORT), instead of the socket in the configuration file
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+
+    /// Print the PXE configuration that would be served, and exit without serving it
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    let config: config::Configuration = serde_yaml::from_reader(File::open(args.configuration)?)?;
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// Human-readable text
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Print the PXE configuration that would be served to a client, and exit
+    Render(RenderArgs),
+    /// Check that the configuration can be served, without serving it. Exits with status 1 if
+    /// there are problems.
+    Check(CheckArgs),
+}
 
-    tracing_subscriber::fmt()
-        .with_max_level(if args.verbose {
+#[derive(clap::Args)]
+struct CheckArgs {
+    /// The configuration file. Parsed as TOML if it has a .toml extension, YAML otherwise.
+    pub config: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct RenderArgs {
+    /// The configuration file. Parsed as TOML if it has a .toml extension, YAML otherwise.
+    #[arg(long)]
+    pub config: PathBuf,
+
+    #[command(flatten)]
+    pub client: RenderClient,
+}
+
+/// The client to render a configuration for. Each of these is accepted in the form that appears
+/// in the PXE configuration path (e.g. pxelinux.cfg/01-aa-bb-cc-dd-ee-ff), or in a more familiar
+/// form (e.g. aa:bb:cc:dd:ee:ff).
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+struct RenderClient {
+    /// The MAC address of the client
+    #[arg(long)]
+    pub mac: Option<String>,
+
+    /// The UUID of the client
+    #[arg(long)]
+    pub uuid: Option<String>,
+
+    /// The IPv4 address of the client
+    #[arg(long)]
+    pub ip: Option<String>,
+}
+
+impl RenderClient {
+    /// The path the client would request its configuration from
+    fn pxe_path(&self) -> PathBuf {
+        let name = if let Some(mac) = &self.mac {
+            let mac = mac.to_lowercase().replace(':', "-");
+            if mac.split('-').count() == 6 {
+                format!("01-{}", mac)
+            } else {
+                mac
+            }
+        } else if let Some(uuid) = &self.uuid {
+            uuid.to_lowercase()
+        } else if let Some(ip) = &self.ip {
+            match ip.parse::<Ipv4Addr>() {
+                Ok(address) => format!("{:08X}", u32::from(address)),
+                Err(_) => ip.to_uppercase(),
+            }
+        } else {
+            "default".to_string()
+        };
+        Path::new("pxelinux.cfg").join(name)
+    }
+}
+
+impl Args {
+    /// Override values in the configuration with those given on the command line
+    fn apply(&self, config: &mut config::Configuration) {
+        if let Some(bind) = self.bind {
+            config.tftp.socket = bind;
+        }
+        // Rendering is for checking the configurations offline, where the boot files may not be
+        if let Some(Command::Render(_)) = self.command {
+            config.on_missing_boot_file = config::MissingBootFilePolicy::Warn;
+        }
+    }
+}
+
+/// Create the server described by the configuration
+fn make_server(config: &config::Configuration) -> anyhow::Result<NetbootServer> {
+    let nfs = config.nfs.clone().unwrap_or_default();
+    let mut server = NetbootServer::with_resolver(Box::new(config.resolver()?), nfs)
+        .with_next_server(config.next_server())
+        .with_generated_tsize(config.tftp.generated_tsize)
+        .with_decompress_gzip(config.tftp.decompress_gzip)
+        .with_config_prefixes(config.tftp.config_prefixes.clone());
+    if let Some(root) = &config.tftp.root {
+        server = server.with_root(root.clone());
+    }
+    if let Some(directory) = &config.tftp.append_directory {
+        server = server.with_append_directory(directory.clone());
+    }
+    Ok(server)
+}
+
+/// Create the subscriber which writes log messages to `writer`
+fn subscriber<W>(
+    verbose: bool,
+    format: LogFormat,
+    writer: W,
+) -> Box<dyn tracing::Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(if verbose {
             tracing::Level::DEBUG
         } else {
             tracing::Level::INFO
         })
-        .with_writer(std::io::stderr)
-        .init();
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().finish()),
+    }
+}
 
-    let boot_configuration = config.tftp.pxe.try_into().unwrap();
-    let server = match config.nfs {
-        Some(nfs) => NetbootServer::with_nfs(boot_configuration, nfs),
-        None => NetbootServer::new(boot_configuration),
+/// Print the PXE configuration served to clients without a more specific configuration
+fn dry_run(server: &NetbootServer, output: &mut impl Write) -> anyhow::Result<()> {
+    block_on(server.render_config_for(&ClientId::Default))?.write_to(output)?;
+    Ok(())
+}
+
+/// Write the file served to a client requesting the PXE configuration at `path`
+fn render(server: &NetbootServer, path: &Path, output: &mut impl Write) -> anyhow::Result<()> {
+    if instant_netboot::pxe_client_id(path)?.is_none() {
+        return Err(anyhow!(
+            "{} is not a PXE configuration path",
+            path.display()
+        ));
+    }
+    let contents = block_on(async {
+        let mut reader = server.tftp_get(path).await?;
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).await?;
+        anyhow::Ok(contents)
+    })?;
+    output.write_all(&contents)?;
+    Ok(())
+}
+
+/// Check that the configuration at `path` can be served, and write a report to `output`. Missing
+/// boot files are problems, whatever `on-missing-boot-file` says. Returns whether there were no
+/// problems.
+fn check(path: &Path, output: &mut impl Write) -> anyhow::Result<bool> {
+    let mut config = match config::Configuration::load(path) {
+        Ok(config) => config,
+        Err(error) => {
+            writeln!(
+                output,
+                "{}: {:#}",
+                path.display(),
+                anyhow::Error::from(error)
+            )?;
+            return Ok(false);
+        }
     };
+
+    let mut problems = Vec::new();
+    if let Ok(missing) = config.missing_boot_files() {
+        problems.extend(
+            missing
+                .iter()
+                .map(|file| format!("boot file {} does not exist", file.display())),
+        );
+    }
+    // Missing boot files were reported above, so they shouldn't hide the problems after them.
+    config.on_missing_boot_file = config::MissingBootFilePolicy::Warn;
+    if let Err(error) = config.validate() {
+        problems.push(format!("{:#}", anyhow::Error::from(error)));
+    }
+
+    for problem in &problems {
+        writeln!(output, "{}: {}", path.display(), problem)?;
+    }
+    if problems.is_empty() {
+        let labels = config.syslinux_configuration()?.labels.len();
+        writeln!(output, "{}: OK, labels: {}", path.display(), labels)?;
+    }
+    Ok(problems.is_empty())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    if let Some(Command::Check(arguments)) = &args.command {
+        let passed = check(&arguments.config, &mut std::io::stdout())?;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    let configuration = match &args.command {
+        Some(Command::Render(render)) => &render.config,
+        Some(Command::Check(_)) => unreachable!("checked without loading the configuration"),
+        None => args
+            .configuration
+            .as_ref()
+            .expect("configuration is required without a subcommand"),
+    };
+    let mut config = config::Configuration::load(configuration)?;
+    args.apply(&mut config);
+
+    tracing::subscriber::set_global_default(subscriber(
+        args.verbose,
+        args.log_format,
+        std::io::stderr,
+    ))?;
+
+    config.validate()?;
+    let server = make_server(&config)?;
+    if let Some(Command::Render(arguments)) = &args.command {
+        return render(
+            &server,
+            &arguments.client.pxe_path(),
+            &mut std::io::stdout(),
+        );
+    }
+    if args.dry_run {
+        return dry_run(&server, &mut std::io::stdout());
+    }
+
+    let shutdown = shutdown::signal()?;
+    let server = Arc::new(server);
     block_on(async {
-        let tftpd = TftpServerBuilder::with_handler(tftp::TftpHandler { server })
-            .bind(config.tftp.socket)
-            .build()
+        let transfers = shutdown::Transfers::default();
+        let builder = TftpServerBuilder::with_handler(tftp::TftpHandler {
+            server: server.clone(),
+            transfers: transfers.clone(),
+            allow_write: config.tftp.allow_write,
+            rate_limiter: config
+                .tftp
+                .rate_limit
+                .map(|limit| tftp::RateLimiter::new(limit.burst, limit.per_second)),
+        });
+        let mut builder = match socket_activation::activated_socket() {
+            Some(socket) => builder.std_socket(socket)?,
+            None => builder.bind(config.tftp.socket),
+        };
+        if let Some(limit) = config.tftp.block_size_limit {
+            builder = builder.block_size_limit(limit);
+        }
+        let tftpd = builder.build().await?;
+        info!("Listening on {}", tftpd.listen_addr()?);
+        // The first service to exit (normally TFTP, on shutdown) stops the others.
+        let mut services =
+            vec![
+                shutdown::serve_until(tftpd, &transfers, shutdown, shutdown::GRACE_PERIOD)
+                    .boxed_local(),
+            ];
+        if let Some(http) = &config.http {
+            let listener = async_std::net::TcpListener::bind(http.socket).await?;
+            info!("Serving HTTP on {}", listener.local_addr()?);
+            services.push(http::serve(listener, server, transfers.clone()).boxed_local());
+        }
+        if let Some(proxy) = config.proxy_dhcp()? {
+            let socket = async_std::net::UdpSocket::bind(
+                config
+                    .proxy_dhcp
+                    .as_ref()
+                    .map(|proxy| proxy.socket)
+                    .unwrap(),
+            )
             .await?;
-        info!("Listening on {}", config.tftp.socket);
-        tftpd.serve().await?;
+            info!("Answering ProxyDHCP on {}", socket.local_addr()?);
+            services.push(proxy_dhcp::serve(socket, proxy).boxed_local());
+        }
+        // Bound last, so that it only answers once the other servers are listening.
+        if let Some(health) = &config.health {
+            let listener = async_std::net::TcpListener::bind(health.socket).await?;
+            info!("Answering health checks on {}", listener.local_addr()?);
+            let labels = config.syslinux_configuration()?.labels.len();
+            services.push(http::serve_health(listener, labels).boxed_local());
+        }
+        futures::future::select_all(services).await.0?;
+        info!("Exiting");
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use instant_netboot::resolver;
+
+    fn configuration() -> config::Configuration {
+        config::Configuration::parse(
+            "tftp:\n  socket: 0.0.0.0:69\n  pxe: linux /Image\n",
+            config::Format::Yaml,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn bind_overrides_configuration() {
+        let mut config = configuration();
+        let args = Args::try_parse_from([
+            "instant-netboot",
+            "--bind",
+            "127.0.0.1:6969",
+            "netboot.yaml",
+        ])
+        .unwrap();
+        args.apply(&mut config);
+        assert_eq!(config.tftp.socket, "127.0.0.1:6969".parse().unwrap());
+    }
+
+    #[test]
+    fn configuration_socket_without_bind() {
+        let mut config = configuration();
+        let args = Args::try_parse_from(["instant-netboot", "netboot.yaml"]).unwrap();
+        args.apply(&mut config);
+        assert_eq!(config.tftp.socket, "0.0.0.0:69".parse().unwrap());
+    }
+
+    #[test]
+    fn dry_run_prints_configuration() {
+        let config = config::Configuration::parse(
+            "tftp:\n  pxe: |\n    title Test\n    linux /Image\n    options quiet\nnfs:\n  host: 192.168.1.1\n  share: /srv/nfs\n  version: 3\n  target-ip: dhcp\n  is-writable: true\n",
+            config::Format::Yaml,
+        )
+        .unwrap();
+        let server = make_server(&config).unwrap();
+
+        let mut output = Vec::new();
+        dry_run(&server, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(
+            "APPEND quiet root=/dev/nfs rw nfsroot=192.168.1.1:/srv/nfs,vers=3,tcp rootwait ip=dhcp\n"
+        ));
+    }
+
+    fn label(name: &str, kernel: &str) -> boot_loader_entries::syslinux::Label {
+        boot_loader_entries::syslinux::Label {
+            name: name.to_string(),
+            kernel: boot_loader_entries::syslinux::Kernel::Kernel(kernel.into()),
+            directives: vec![],
+        }
+    }
+
+    /// A server which serves a rescue label to one MAC address, and a default label otherwise
+    fn render_server() -> NetbootServer {
+        let mut resolver = resolver::MapResolver::new(label("default", "/Image").into());
+        resolver.insert(
+            ClientId::Mac([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]),
+            label("rescue", "/rescue/Image").into(),
+        );
+        NetbootServer::with_resolver(Box::new(resolver), Default::default())
+    }
+
+    fn render_to_string(server: &NetbootServer, args: &[&str]) -> String {
+        let args = Args::try_parse_from(
+            ["instant-netboot", "render", "--config", "netboot.yaml"]
+                .iter()
+                .chain(args),
+        )
+        .unwrap();
+        let Some(Command::Render(render_args)) = args.command else {
+            panic!("expected the render subcommand");
+        };
+        let mut output = Vec::new();
+        render(server, &render_args.client.pxe_path(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn render_mac() {
+        let server = render_server();
+        let expected = "LABEL rescue\nKERNEL /rescue/Image\n";
+        assert_eq!(
+            render_to_string(&server, &["--mac", "01-aa-bb-cc-dd-ee-ff"]),
+            expected
+        );
+        assert_eq!(
+            render_to_string(&server, &["--mac", "AA:BB:CC:DD:EE:FF"]),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_unknown_mac_falls_back_to_default() {
+        let server = render_server();
+        assert_eq!(
+            render_to_string(&server, &["--mac", "01-88-99-aa-bb-cc-dd"]),
+            "LABEL default\nKERNEL /Image\n"
+        );
+    }
+
+    #[test]
+    fn render_uuid() {
+        let server = render_server();
+        assert_eq!(
+            render_to_string(&server, &["--uuid", "B8945908-D6A6-41A9-611D-74A6AB80B83D"]),
+            "LABEL default\nKERNEL /Image\n"
+        );
+    }
+
+    #[test]
+    fn render_ip() {
+        let server = render_server();
+        let expected = "LABEL default\nKERNEL /Image\n";
+        assert_eq!(
+            render_to_string(&server, &["--ip", "192.168.2.91"]),
+            expected
+        );
+        assert_eq!(render_to_string(&server, &["--ip", "C0A8025B"]), expected);
+    }
+
+    #[test]
+    fn render_missing_boot_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("netboot.yaml");
+        std::fs::write(
+            &path,
+            "tftp:\n  pxe: |\n    title Fedora\n    linux /missing/Image\n",
+        )
+        .unwrap();
+        let args = Args::try_parse_from([
+            "instant-netboot",
+            "render",
+            "--config",
+            path.to_str().unwrap(),
+            "--ip",
+            "192.168.2.91",
+        ])
+        .unwrap();
+        let Some(Command::Render(render_args)) = &args.command else {
+            panic!("expected the render subcommand");
+        };
+
+        let mut config = config::Configuration::load(&path).unwrap();
+        assert!(config.validate().is_err());
+        args.apply(&mut config);
+        config.validate().unwrap();
+        let server = make_server(&config).unwrap();
+        let mut output = Vec::new();
+        render(&server, &render_args.client.pxe_path(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "DEFAULT Fedora\nLABEL Fedora\nKERNEL /missing/Image\n"
+        );
+    }
+
+    #[test]
+    fn render_invalid_client() {
+        let server = render_server();
+        let mut output = Vec::new();
+        let result = render(
+            &server,
+            &RenderClient {
+                mac: Some("not-a-mac".to_string()),
+                uuid: None,
+                ip: None,
+            }
+            .pxe_path(),
+            &mut output,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn render_requires_one_client() {
+        assert!(
+            Args::try_parse_from(["instant-netboot", "render", "--config", "netboot.yaml"])
+                .is_err()
+        );
+        assert!(Args::try_parse_from([
+            "instant-netboot",
+            "render",
+            "--config",
+            "netboot.yaml",
+            "--mac",
+            "01-aa-bb-cc-dd-ee-ff",
+            "--ip",
+            "192.168.2.91",
+        ])
+        .is_err());
+    }
+
+    /// Log messages written by a subscriber
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for LogBuffer {
+        type Writer = LogBuffer;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn json_logs() {
+        use async_tftp::server::Handler;
+
+        let mut handler = tftp::TftpHandler {
+            server: render_server().into(),
+            transfers: shutdown::Transfers::default(),
+            allow_write: false,
+            rate_limiter: None,
+        };
+        let client = "192.168.1.100:1024".parse().unwrap();
+        let logs = LogBuffer::default();
+        tracing::subscriber::with_default(subscriber(true, LogFormat::Json, logs.clone()), || {
+            block_on(handler.read_req_open(&client, Path::new("pxelinux.cfg/default"))).unwrap();
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let record: serde_json::Value = serde_json::from_str(logs.lines().next().unwrap()).unwrap();
+        assert_eq!(record["level"], "DEBUG");
+        assert_eq!(record["fields"]["message"], "GET");
+        assert_eq!(record["span"]["client"], "192.168.1.100:1024");
+        assert_eq!(record["span"]["path"], "pxelinux.cfg/default");
+    }
+
+    /// The fields of the request spans closed while `f` runs
+    fn closed_request_spans(f: impl FnOnce()) -> Vec<serde_json::Value> {
+        let logs = LogBuffer::default();
+        tracing::subscriber::with_default(subscriber(true, LogFormat::Json, logs.clone()), f);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        logs.lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|record| record["fields"]["message"] == "close")
+            .map(|record| record["span"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn request_span() {
+        use async_tftp::server::Handler;
+
+        let mut handler = tftp::TftpHandler {
+            server: render_server().into(),
+            transfers: shutdown::Transfers::default(),
+            allow_write: false,
+            rate_limiter: None,
+        };
+        let client = "192.168.1.100:1024".parse().unwrap();
+        let spans = closed_request_spans(|| {
+            let (mut reader, _) =
+                block_on(handler.read_req_open(&client, Path::new("pxelinux.cfg/default")))
+                    .unwrap();
+            block_on(reader.read_to_end(&mut Vec::new())).unwrap();
+            drop(reader);
+            let result = block_on(handler.read_req_open(&client, Path::new("missing")));
+            assert!(result.is_err());
+        });
+
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0]["name"], "request");
+        assert_eq!(spans[0]["client"], "192.168.1.100:1024");
+        assert_eq!(spans[0]["path"], "pxelinux.cfg/default");
+        assert_eq!(spans[0]["action"], "pxe-config");
+        assert_eq!(spans[0]["bytes"], 28);
+        assert!(spans[0]["duration_ms"].is_u64());
+        assert_eq!(spans[1]["path"], "missing");
+        assert_eq!(spans[1]["action"], "not-found");
+    }
+
+    /// Check a configuration serving a kernel, written to a temporary directory
+    fn check_kernel(create_kernel: bool) -> (bool, String) {
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        if create_kernel {
+            std::fs::write(&kernel, "kernel").unwrap();
+        }
+        let path = directory.path().join("netboot.yaml");
+        std::fs::write(
+            &path,
+            format!(
+                "tftp:\n  pxe: |\n    title Test\n    linux {}\n",
+                kernel.display()
+            ),
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+        let passed = check(&path, &mut output).unwrap();
+        let output = String::from_utf8(output)
+            .unwrap()
+            .replace(&directory.path().display().to_string(), "");
+        (passed, output)
+    }
+
+    #[test]
+    fn check_clean_configuration() {
+        assert!(Args::try_parse_from(["instant-netboot", "check", "netboot.yaml"]).is_ok());
+        assert_eq!(
+            check_kernel(true),
+            (true, "/netboot.yaml: OK, labels: 1\n".to_string())
+        );
+    }
+
+    #[test]
+    fn check_missing_kernel() {
+        assert_eq!(
+            check_kernel(false),
+            (
+                false,
+                "/netboot.yaml: boot file /Image does not exist\n".to_string()
+            )
+        );
+
+        let mut output = Vec::new();
+        assert!(!check(Path::new("/nonexistent/netboot.yaml"), &mut output).unwrap());
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("/nonexistent/netboot.yaml: failed to read"));
+    }
+
+    #[test]
+    fn invalid_bind_address() {
+        let result =
+            Args::try_parse_from(["instant-netboot", "--bind", "localhost", "netboot.yaml"]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use async_std::net::UdpSocket;
+
+/// The start of the options in a DHCP packet, after the fixed BOOTP fields and the magic cookie
+const OPTIONS_OFFSET: usize = 240;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const FILE_OFFSET: usize = 108;
+const FILE_LENGTH: usize = 128;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_END: u8 = 255;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_IDENTIFIER: u8 = 54;
+const OPTION_VENDOR_CLASS: u8 = 60;
+const OPTION_VENDOR_SPECIFIC: u8 = 43;
+const OPTION_CLIENT_ARCHITECTURE: u8 = 93;
+const OPTION_CLIENT_UUID: u8 = 97;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+
+/// PXE discovery control (vendor sub-option 6): skip boot server discovery, and download the boot
+/// file given in the offer.
+const PXE_DISCOVERY_CONTROL: [u8; 4] = [6, 1, 8, OPTION_END];
+
+/// What a ProxyDHCP server tells PXE clients
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyDhcp {
+    /// The address of the TFTP server
+    pub server: Ipv4Addr,
+    /// The boot file for BIOS clients
+    pub boot_file: String,
+    /// The boot file for UEFI clients. Defaults to `boot_file`.
+    pub uefi_boot_file: Option<String>,
+}
+
+/// Find an option in the options of a DHCP packet
+fn find_option(options: &[u8], code: u8) -> Option<&[u8]> {
+    let mut index = 0;
+    while index < options.len() {
+        match options[index] {
+            OPTION_PAD => index += 1,
+            OPTION_END => return None,
+            current => {
+                let length = *options.get(index + 1)? as usize;
+                let value = options.get(index + 2..index + 2 + length)?;
+                if current == code {
+                    return Some(value);
+                }
+                index += 2 + length;
+            }
+        }
+    }
+    None
+}
+
+/// The architecture of the client (DHCP option 93), where 0 is a BIOS client and everything else
+/// is some kind of UEFI client
+fn architecture(options: &[u8]) -> Option<u16> {
+    let value = find_option(options, OPTION_CLIENT_ARCHITECTURE)?;
+    Some(u16::from_be_bytes(value.try_into().ok()?))
+}
+
+impl ProxyDhcp {
+    /// The boot file for a client of this architecture
+    fn boot_file(&self, architecture: Option<u16>) -> &str {
+        match (architecture, &self.uefi_boot_file) {
+            (Some(architecture), Some(uefi_boot_file)) if architecture != 0 => uefi_boot_file,
+            _ => &self.boot_file,
+        }
+    }
+
+    /// The offer for a DHCP packet, if it is a DHCPDISCOVER from a PXE client. The offer doesn't
+    /// assign an address, that's left to the real DHCP server.
+    pub fn offer(&self, request: &[u8]) -> Option<Vec<u8>> {
+        if request.len() < OPTIONS_OFFSET || request[0] != 1 || request[236..240] != MAGIC_COOKIE {
+            return None;
+        }
+        let options = &request[OPTIONS_OFFSET..];
+        if find_option(options, OPTION_MESSAGE_TYPE)? != [DHCPDISCOVER] {
+            return None;
+        }
+        if !find_option(options, OPTION_VENDOR_CLASS)?.starts_with(b"PXEClient") {
+            return None;
+        }
+
+        let mut reply = vec![0; OPTIONS_OFFSET];
+        reply[0] = 2;
+        // htype and hlen
+        reply[1..3].copy_from_slice(&request[1..3]);
+        // xid
+        reply[4..8].copy_from_slice(&request[4..8]);
+        // flags
+        reply[10..12].copy_from_slice(&request[10..12]);
+        // siaddr: the next server, from which to download the boot file
+        reply[20..24].copy_from_slice(&self.server.octets());
+        // giaddr and chaddr
+        reply[24..44].copy_from_slice(&request[24..44]);
+        let file = self.boot_file(architecture(options)).as_bytes();
+        let length = file.len().min(FILE_LENGTH - 1);
+        reply[FILE_OFFSET..FILE_OFFSET + length].copy_from_slice(&file[..length]);
+        reply[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut option = |code: u8, value: &[u8]| {
+            reply.push(code);
+            reply.push(value.len() as u8);
+            reply.extend_from_slice(value);
+        };
+        option(OPTION_MESSAGE_TYPE, &[DHCPOFFER]);
+        option(OPTION_SERVER_IDENTIFIER, &self.server.octets());
+        option(OPTION_VENDOR_CLASS, b"PXEClient");
+        if let Some(uuid) = find_option(options, OPTION_CLIENT_UUID) {
+            option(OPTION_CLIENT_UUID, uuid);
+        }
+        option(OPTION_VENDOR_SPECIFIC, &PXE_DISCOVERY_CONTROL);
+        reply.push(OPTION_END);
+        Some(reply)
+    }
+}
+
+/// Where to send the reply to a request. Clients don't have an address yet, so replies are
+/// broadcast, unless the request came through a relay agent.
+fn reply_address(request: &[u8]) -> SocketAddr {
+    let relay = Ipv4Addr::new(request[24], request[25], request[26], request[27]);
+    match relay.is_unspecified() {
+        true => (Ipv4Addr::BROADCAST, 68).into(),
+        false => (relay, 67).into(),
+    }
+}
+
+/// Answer PXE clients' DHCP discovery with the boot file, alongside the network's DHCP server.
+/// Clients that want to discover boot servers (on port 4011) aren't supported.
+pub async fn serve(socket: UdpSocket, proxy: ProxyDhcp) -> anyhow::Result<()> {
+    socket.set_broadcast(true)?;
+    let mut buffer = [0; 1500];
+    loop {
+        let (length, client) = socket.recv_from(&mut buffer).await?;
+        let request = &buffer[..length];
+        let Some(offer) = proxy.offer(request) else {
+            continue;
+        };
+        let architecture = architecture(&request[OPTIONS_OFFSET..]);
+        tracing::debug!(%client, boot_file = proxy.boot_file(architecture), "ProxyDHCP offer");
+        if let Err(error) = socket.send_to(&offer, reply_address(request)).await {
+            tracing::warn!("Failed to send ProxyDHCP offer: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A DHCPDISCOVER from a PXE client with this architecture
+    fn discover(architecture: u16) -> Vec<u8> {
+        let mut packet = vec![0; OPTIONS_OFFSET];
+        packet[0] = 1;
+        packet[1] = 1;
+        packet[2] = 6;
+        packet[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        packet[10] = 0x80;
+        packet[28..34].copy_from_slice(&[0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd]);
+        packet[236..240].copy_from_slice(&MAGIC_COOKIE);
+        packet.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER]);
+        packet.extend_from_slice(&[OPTION_CLIENT_ARCHITECTURE, 2]);
+        packet.extend_from_slice(&architecture.to_be_bytes());
+        packet.extend_from_slice(&[OPTION_VENDOR_CLASS, 32]);
+        packet.extend_from_slice(b"PXEClient:Arch:00000:UNDI:002001");
+        packet.extend_from_slice(&[OPTION_CLIENT_UUID, 3, 0, 1, 2]);
+        packet.push(OPTION_END);
+        packet
+    }
+
+    fn proxy() -> ProxyDhcp {
+        ProxyDhcp {
+            server: Ipv4Addr::new(192, 168, 1, 10),
+            boot_file: "pxelinux.0".to_string(),
+            uefi_boot_file: Some("grubx64.efi".to_string()),
+        }
+    }
+
+    /// The boot file in a reply
+    fn file(reply: &[u8]) -> &str {
+        let file = &reply[FILE_OFFSET..FILE_OFFSET + FILE_LENGTH];
+        let end = file.iter().position(|byte| *byte == 0).unwrap();
+        std::str::from_utf8(&file[..end]).unwrap()
+    }
+
+    #[test]
+    fn offer() {
+        let request = discover(0);
+        let reply = proxy().offer(&request).unwrap();
+        assert_eq!(reply[0], 2);
+        assert_eq!(reply[4..8], request[4..8]);
+        assert_eq!(reply[10..12], request[10..12]);
+        assert_eq!(reply[16..20], [0, 0, 0, 0]);
+        assert_eq!(reply[20..24], [192, 168, 1, 10]);
+        assert_eq!(reply[28..44], request[28..44]);
+        assert_eq!(file(&reply), "pxelinux.0");
+
+        let options = &reply[OPTIONS_OFFSET..];
+        assert_eq!(
+            find_option(options, OPTION_MESSAGE_TYPE),
+            Some(&[DHCPOFFER][..])
+        );
+        assert_eq!(
+            find_option(options, OPTION_SERVER_IDENTIFIER),
+            Some(&[192, 168, 1, 10][..])
+        );
+        assert_eq!(
+            find_option(options, OPTION_VENDOR_CLASS),
+            Some(&b"PXEClient"[..])
+        );
+        assert_eq!(
+            find_option(options, OPTION_CLIENT_UUID),
+            Some(&[0, 1, 2][..])
+        );
+        assert_eq!(
+            find_option(options, OPTION_VENDOR_SPECIFIC),
+            Some(&PXE_DISCOVERY_CONTROL[..])
+        );
+        assert_eq!(
+            reply_address(&request),
+            "255.255.255.255:68".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn uefi_boot_file() {
+        let reply = proxy().offer(&discover(7)).unwrap();
+        assert_eq!(file(&reply), "grubx64.efi");
+
+        let proxy = ProxyDhcp {
+            uefi_boot_file: None,
+            ..proxy()
+        };
+        let reply = proxy.offer(&discover(7)).unwrap();
+        assert_eq!(file(&reply), "pxelinux.0");
+    }
+
+    #[test]
+    fn ignore_other_requests() {
+        // Not a PXE client
+        let mut request = discover(0);
+        let vendor = OPTIONS_OFFSET + 3 + 4 + 2;
+        request[vendor..vendor + 9].copy_from_slice(b"MSFT 5.0 ");
+        assert_eq!(proxy().offer(&request), None);
+
+        // A DHCPREQUEST, which the real DHCP server answers
+        let mut request = discover(0);
+        request[OPTIONS_OFFSET + 2] = 3;
+        assert_eq!(proxy().offer(&request), None);
+
+        // Truncated
+        assert_eq!(proxy().offer(&discover(0)[..200]), None);
+    }
+
+    #[test]
+    fn relayed_request() {
+        let mut request = discover(0);
+        request[24..28].copy_from_slice(&[10, 0, 0, 1]);
+        assert_eq!(reply_address(&request), "10.0.0.1:67".parse().unwrap());
+        let reply = proxy().offer(&request).unwrap();
+        assert_eq!(reply[24..28], [10, 0, 0, 1]);
+    }
+}
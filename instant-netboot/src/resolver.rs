@@ -0,0 +1,275 @@
+use std::{collections::HashMap, fmt, net::Ipv4Addr, str::FromStr};
+
+use boot_loader_entries::syslinux;
+use serde::{de, Deserialize};
+
+use crate::instant_netboot::ClientId;
+
+/// Selects the boot configuration served to each PXE client
+pub trait ConfigResolver: Send + Sync {
+    /// Select the configuration served to this client. If this returns None, the client is told
+    /// that no configuration exists for it.
+    fn resolve(&self, client: &ClientId) -> Option<&syslinux::Configuration>;
+
+    /// Select the configuration served to this client when it requests its configuration from a
+    /// prefix for `architecture` (e.g. `aa64`). By default, the architecture makes no difference.
+    fn resolve_for_architecture(
+        &self,
+        client: &ClientId,
+        architecture: &str,
+    ) -> Option<&syslinux::Configuration> {
+        let _ = architecture;
+        self.resolve(client)
+    }
+
+    /// All of the labels in the configurations this resolver may select. Only boot files
+    /// referenced by one of these labels are served.
+    fn labels(&self) -> Box<dyn Iterator<Item = &syslinux::Label> + '_>;
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("invalid IPv4 address or CIDR range: \"{0}\"")]
+pub struct InvalidNetwork(String);
+
+/// A range of IPv4 addresses, such as 192.168.1.0/24. A single address is a /32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv4Network {
+    address: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Ipv4Network {
+    fn mask(prefix: u8) -> u32 {
+        u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+    }
+
+    /// Whether `address` is in this range
+    pub fn contains(&self, address: &Ipv4Addr) -> bool {
+        u32::from(*address) & Self::mask(self.prefix) == u32::from(self.address)
+    }
+}
+
+impl FromStr for Ipv4Network {
+    type Err = InvalidNetwork;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidNetwork(input.to_string());
+        let (address, prefix) = match input.split_once('/') {
+            Some((address, prefix)) => (address, prefix.parse().map_err(|_| invalid())?),
+            None => (input, 32),
+        };
+        let address: Ipv4Addr = address.parse().map_err(|_| invalid())?;
+        if prefix > 32 {
+            return Err(invalid());
+        }
+        Ok(Ipv4Network {
+            address: (u32::from(address) & Self::mask(prefix)).into(),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Ipv4Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv4Network {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let value: String = Deserialize::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Selects configurations from a static table, falling back to a default configuration for
+/// clients that aren't in the table.
+#[derive(Clone, Debug)]
+pub struct MapResolver {
+    default: syslinux::Configuration,
+    clients: HashMap<ClientId, syslinux::Configuration>,
+    networks: Vec<(Ipv4Network, syslinux::Configuration)>,
+    architectures: HashMap<String, syslinux::Configuration>,
+}
+
+impl MapResolver {
+    pub fn new(default: syslinux::Configuration) -> Self {
+        Self {
+            default,
+            clients: HashMap::new(),
+            networks: Vec::new(),
+            architectures: HashMap::new(),
+        }
+    }
+
+    /// Serve `configuration` instead of the default configuration to clients requesting their
+    /// configuration from a prefix for `architecture`. Clients inserted with
+    /// [MapResolver::insert] or [MapResolver::insert_network] take precedence.
+    pub fn insert_architecture(
+        &mut self,
+        architecture: String,
+        configuration: syslinux::Configuration,
+    ) {
+        self.architectures.insert(architecture, configuration);
+    }
+
+    /// The configuration for this client in particular, if there is one
+    fn resolve_client(&self, client: &ClientId) -> Option<&syslinux::Configuration> {
+        if let Some(configuration) = self.clients.get(client) {
+            return Some(configuration);
+        }
+        match client {
+            ClientId::Ip(address) => self
+                .networks
+                .iter()
+                .filter(|(network, _)| network.contains(address))
+                .max_by_key(|(network, _)| network.prefix)
+                .map(|(_, configuration)| configuration),
+            _ => None,
+        }
+    }
+
+    /// Serve `configuration` to clients requesting their configuration by an IP address in
+    /// `network`. If several networks contain the address, the smallest one is chosen. Clients
+    /// inserted with [MapResolver::insert] take precedence.
+    pub fn insert_network(&mut self, network: Ipv4Network, configuration: syslinux::Configuration) {
+        self.networks.push((network, configuration));
+    }
+
+    /// Serve `configuration` to `client` instead of the default configuration.
+    // TODO: Per-client configurations can't be given in the configuration file yet
+    #[allow(dead_code)]
+    pub fn insert(&mut self, client: ClientId, configuration: syslinux::Configuration) {
+        self.clients.insert(client, configuration);
+    }
+}
+
+impl ConfigResolver for MapResolver {
+    fn resolve(&self, client: &ClientId) -> Option<&syslinux::Configuration> {
+        Some(self.resolve_client(client).unwrap_or(&self.default))
+    }
+
+    fn resolve_for_architecture(
+        &self,
+        client: &ClientId,
+        architecture: &str,
+    ) -> Option<&syslinux::Configuration> {
+        self.resolve_client(client)
+            .or_else(|| self.architectures.get(architecture))
+            .or_else(|| self.resolve(client))
+    }
+
+    fn labels(&self) -> Box<dyn Iterator<Item = &syslinux::Label> + '_> {
+        Box::new(
+            [&self.default]
+                .into_iter()
+                .chain(self.clients.values())
+                .chain(self.networks.iter().map(|(_, configuration)| configuration))
+                .chain(self.architectures.values())
+                .flat_map(|configuration| &configuration.labels),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn label(name: &str) -> syslinux::Configuration {
+        syslinux::Label {
+            name: name.to_string(),
+            kernel: syslinux::Kernel::Kernel(format!("/{}/Image", name).into()),
+            directives: vec![],
+        }
+        .into()
+    }
+
+    #[test]
+    fn map_resolver() {
+        let mac = ClientId::Mac([0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd]);
+        let mut resolver = MapResolver::new(label("default"));
+        resolver.insert(mac.clone(), label("rescue"));
+
+        assert_eq!(resolver.resolve(&mac), Some(&label("rescue")));
+        assert_eq!(
+            resolver.resolve(&ClientId::Ip("192.168.1.1".parse().unwrap())),
+            Some(&label("default"))
+        );
+        assert_eq!(
+            resolver.resolve(&ClientId::Default),
+            Some(&label("default"))
+        );
+
+        let mut labels = resolver
+            .labels()
+            .map(|l| l.name.as_str())
+            .collect::<Vec<_>>();
+        labels.sort();
+        assert_eq!(labels, vec!["default", "rescue"]);
+    }
+
+    #[test]
+    fn ipv4_network() {
+        let network: Ipv4Network = "192.168.1.0/24".parse().unwrap();
+        assert!(network.contains(&"192.168.1.1".parse().unwrap()));
+        assert!(!network.contains(&"192.168.2.1".parse().unwrap()));
+        assert_eq!(
+            "192.168.1.7/24".parse::<Ipv4Network>().unwrap().to_string(),
+            "192.168.1.0/24"
+        );
+        assert_eq!(
+            "192.168.1.7".parse::<Ipv4Network>().unwrap().to_string(),
+            "192.168.1.7/32"
+        );
+        assert!("0.0.0.0/0"
+            .parse::<Ipv4Network>()
+            .unwrap()
+            .contains(&"10.0.0.1".parse().unwrap()));
+        for input in ["192.168.1.0/33", "192.168.1/24", "192.168.1.0/", "default"] {
+            assert_eq!(
+                input.parse::<Ipv4Network>(),
+                Err(InvalidNetwork(input.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn map_resolver_networks() {
+        let mut resolver = MapResolver::new(label("default"));
+        resolver.insert_network("192.168.1.0/24".parse().unwrap(), label("lab"));
+        resolver.insert_network("192.168.1.128/25".parse().unwrap(), label("rescue"));
+        resolver.insert(
+            ClientId::Ip("192.168.1.200".parse().unwrap()),
+            label("single"),
+        );
+
+        let client_id = |path: &str| {
+            crate::instant_netboot::pxe_client_id(std::path::Path::new(path))
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(
+            client_id("pxelinux.cfg/C0A80101"),
+            ClientId::Ip("192.168.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            resolver.resolve(&client_id("pxelinux.cfg/C0A80101")),
+            Some(&label("lab"))
+        );
+        assert_eq!(
+            resolver.resolve(&client_id("pxelinux.cfg/C0A80181")),
+            Some(&label("rescue"))
+        );
+        assert_eq!(
+            resolver.resolve(&client_id("pxelinux.cfg/C0A801C8")),
+            Some(&label("single"))
+        );
+        assert_eq!(
+            resolver.resolve(&client_id("pxelinux.cfg/C0A80201")),
+            Some(&label("default"))
+        );
+    }
+}
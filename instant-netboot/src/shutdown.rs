@@ -0,0 +1,200 @@
+use std::{
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_tftp::server::{Handler, TftpServer};
+use futures::StreamExt;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook_async_std::Signals;
+use tracing::{info, warn};
+
+/// How long transfers in progress are given to finish after a shutdown is requested
+pub const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct TransferState {
+    draining: AtomicBool,
+    in_progress: AtomicUsize,
+}
+
+/// Counts the transfers in progress, so that they can finish before the server exits.
+#[derive(Clone, Debug, Default)]
+pub struct Transfers {
+    state: Arc<TransferState>,
+}
+
+/// A transfer in progress. The transfer is finished when this is dropped.
+#[derive(Debug)]
+pub struct Transfer {
+    state: Arc<TransferState>,
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.state.in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Transfers {
+    /// Start a new transfer. Returns None if the server is shutting down.
+    pub fn start(&self) -> Option<Transfer> {
+        self.state.in_progress.fetch_add(1, Ordering::SeqCst);
+        let transfer = Transfer {
+            state: self.state.clone(),
+        };
+        (!self.state.draining.load(Ordering::SeqCst)).then_some(transfer)
+    }
+
+    /// The number of transfers in progress
+    pub fn in_progress(&self) -> usize {
+        self.state.in_progress.load(Ordering::SeqCst)
+    }
+
+    /// Refuse new transfers, and wait up to `timeout` for the transfers in progress to finish.
+    /// Returns false if some transfers did not finish in time.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.state.draining.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while self.in_progress() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+        true
+    }
+}
+
+/// Handle SIGINT and SIGTERM. The returned future completes when either is received.
+pub fn signal() -> io::Result<impl Future<Output = ()>> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    Ok(async move {
+        if let Some(signal) = signals.next().await {
+            info!("Received signal {}", signal);
+        }
+    })
+}
+
+/// Serve requests until `shutdown` completes, then give the transfers in progress up to
+/// `grace_period` to finish.
+pub async fn serve_until<H, F>(
+    tftpd: TftpServer<H>,
+    transfers: &Transfers,
+    shutdown: F,
+    grace_period: Duration,
+) -> anyhow::Result<()>
+where
+    H: Handler + 'static,
+    F: Future<Output = ()>,
+{
+    let serve = async { Ok(tftpd.serve().await?) };
+    let stop = async {
+        shutdown.await;
+        info!(
+            "Shutting down, waiting for {} transfer(s) to finish",
+            transfers.in_progress()
+        );
+        if transfers.drain(grace_period).await {
+            info!("All transfers finished");
+        } else {
+            warn!(
+                "Abandoning {} transfer(s) in progress",
+                transfers.in_progress()
+            );
+        }
+        Ok(())
+    };
+    futures::pin_mut!(serve, stop);
+    futures::future::select(serve, stop).await.factor_first().0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::task::block_on;
+    use async_tftp::server::TftpServerBuilder;
+    use futures::io::{Empty, Sink};
+
+    #[test]
+    fn drain_waits_for_transfers() {
+        let transfers = Transfers::default();
+        let transfer = transfers.start().unwrap();
+        assert_eq!(transfers.in_progress(), 1);
+
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(transfer);
+        });
+        assert!(block_on(transfers.drain(Duration::from_secs(5))));
+        assert_eq!(transfers.in_progress(), 0);
+        assert!(transfers.start().is_none());
+        assert_eq!(transfers.in_progress(), 0);
+    }
+
+    #[test]
+    fn drain_times_out() {
+        let transfers = Transfers::default();
+        let _transfer = transfers.start().unwrap();
+        assert!(!block_on(transfers.drain(Duration::from_millis(50))));
+    }
+
+    struct NullHandler;
+
+    #[async_trait::async_trait]
+    impl Handler for NullHandler {
+        type Reader = Empty;
+        type Writer = Sink;
+
+        async fn read_req_open(
+            &mut self,
+            _client: &std::net::SocketAddr,
+            _path: &std::path::Path,
+        ) -> Result<(Self::Reader, Option<u64>), async_tftp::packet::Error> {
+            Err(async_tftp::packet::Error::FileNotFound)
+        }
+
+        async fn write_req_open(
+            &mut self,
+            _client: &std::net::SocketAddr,
+            _path: &std::path::Path,
+            _size: Option<u64>,
+        ) -> Result<Self::Writer, async_tftp::packet::Error> {
+            Err(async_tftp::packet::Error::IllegalOperation)
+        }
+    }
+
+    #[test]
+    fn signal_stops_server() {
+        block_on(async {
+            let tftpd = TftpServerBuilder::with_handler(NullHandler)
+                .bind("127.0.0.1:0".parse().unwrap())
+                .build()
+                .await
+                .unwrap();
+            let transfers = Transfers::default();
+            let transfer = transfers.start().unwrap();
+
+            let shutdown = signal().unwrap();
+            let raise = async {
+                signal_hook::low_level::raise(SIGTERM).unwrap();
+                async_std::task::sleep(Duration::from_millis(50)).await;
+                drop(transfer);
+            };
+
+            let start = Instant::now();
+            let (result, ()) = futures::join!(
+                serve_until(tftpd, &transfers, shutdown, GRACE_PERIOD),
+                raise
+            );
+            result.unwrap();
+            assert!(start.elapsed() < GRACE_PERIOD);
+            assert_eq!(transfers.in_progress(), 0);
+        });
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    net::UdpSocket,
+    os::fd::{FromRawFd, RawFd},
+};
+
+/// The first file descriptor passed by the service manager (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the file descriptor of the first socket passed by the service manager, given the values
+/// of `LISTEN_PID` and `LISTEN_FDS`. Returns None if the sockets were not meant for this process.
+fn listen_fd(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<RawFd> {
+    let listen_pid: u32 = listen_pid?.parse().ok()?;
+    let listen_fds: u32 = listen_fds?.parse().ok()?;
+    (listen_pid == pid && listen_fds >= 1).then_some(LISTEN_FDS_START)
+}
+
+/// Take the UDP socket passed by systemd, if this process was socket-activated. Subsequent calls
+/// return None.
+pub fn activated_socket() -> Option<UdpSocket> {
+    let fd = listen_fd(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )?;
+    // Like sd_listen_fds(1), so that the socket is not taken twice, or by child processes.
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    // SAFETY: The service manager passed ownership of this descriptor to this process, and we've
+    // ensured that it will not be taken again.
+    Some(unsafe { UdpSocket::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn socket_activated() {
+        assert_eq!(listen_fd(Some("1234"), Some("1"), 1234), Some(3));
+        assert_eq!(listen_fd(Some("1234"), Some("2"), 1234), Some(3));
+    }
+
+    #[test]
+    fn not_socket_activated() {
+        assert_eq!(listen_fd(None, None, 1234), None);
+        assert_eq!(listen_fd(Some("1234"), None, 1234), None);
+        assert_eq!(listen_fd(Some("1234"), Some("0"), 1234), None);
+        // The sockets were meant for another process
+        assert_eq!(listen_fd(Some("4321"), Some("1"), 1234), None);
+        assert_eq!(listen_fd(Some("foo"), Some("1"), 1234), None);
+    }
+}
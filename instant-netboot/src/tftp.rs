@@ -1,30 +1,179 @@
-use std::{net::SocketAddr, path::Path};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 use async_tftp::packet;
-use futures::AsyncRead;
+use futures::{ready, AsyncRead};
+use tracing::{field, Instrument};
 
-use crate::instant_netboot;
+use crate::{instant_netboot, shutdown};
 
 /// Adapter for async_tftp
-pub(crate) struct TftpHandler {
-    pub server: instant_netboot::NetbootServer,
+pub struct TftpHandler {
+    pub server: Arc<instant_netboot::NetbootServer>,
+    pub transfers: shutdown::Transfers,
+    /// Whether writes are allowed. Netboot is read-only, so this only changes how they are refused.
+    pub allow_write: bool,
+    /// Limits the rate of requests from each client, if given
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+/// When a request is answered, according to the [RateLimiter]
+#[derive(Debug, PartialEq)]
+pub enum Admission {
+    Allowed,
+    /// Answered after `wait`, and `first` is true if the previous request from this client was
+    /// allowed
+    Delayed {
+        wait: Duration,
+        first: bool,
+    },
+}
+
+/// The requests a client may make right now. Tokens go negative when requests are delayed, until
+/// they are paid back.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    delaying: bool,
+}
+
+/// Limits the rate of requests from each client address with a token bucket. A client may make
+/// `burst` requests at once, and `per_second` requests a second after that. A client that
+/// retries a few times after losing packets stays within the burst. Requests over the limit are
+/// delayed rather than refused, because a PXE ROM gives up on the first error it receives.
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: f64,
+    per_second: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        RateLimiter {
+            burst: burst.into(),
+            per_second,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take a token for a request from `client` at `now`
+    pub fn admit(&mut self, client: IpAddr, now: Instant) -> Admission {
+        let (burst, per_second) = (self.burst, self.per_second);
+        let refill = |bucket: &mut Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+            bucket.updated = now;
+        };
+        // Clients with a full bucket are the same as clients that haven't been seen, so forget
+        // them, rather than remembering every client forever.
+        self.buckets.retain(|_, bucket| {
+            refill(bucket);
+            bucket.tokens < burst
+        });
+
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            delaying: false,
+        });
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            bucket.delaying = false;
+            Admission::Allowed
+        } else {
+            let first = !bucket.delaying;
+            bucket.delaying = true;
+            Admission::Delayed {
+                wait: Duration::from_secs_f64(-bucket.tokens / per_second),
+                first,
+            }
+        }
+    }
+}
+
+/// A file being sent to a client. The transfer is finished when this is dropped, at which point
+/// the number of bytes served, the block size and the duration of the transfer are recorded in the
+/// request span.
+pub struct TransferReader {
+    reader: Box<dyn instant_netboot::FileReader>,
+    /// Waited out before the first block is read, if the request was delayed by the rate limit
+    delay: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    _transfer: shutdown::Transfer,
+    span: tracing::Span,
+    start: Instant,
+    bytes: u64,
+    block_size: Option<usize>,
+}
+
+impl AsyncRead for TransferReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        // async_tftp reads each block into a buffer of the block size negotiated with the client,
+        // and doesn't otherwise tell us what it is.
+        self.block_size.get_or_insert(buf.len());
+        if let Some(delay) = &mut self.delay {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        let result = Pin::new(&mut self.reader).poll_read(cx, buf);
+        if let Poll::Ready(Ok(length)) = result {
+            self.bytes += length as u64;
+        }
+        result
+    }
+}
+
+impl Drop for TransferReader {
+    fn drop(&mut self) {
+        self.span.record("bytes", self.bytes);
+        if let Some(block_size) = self.block_size {
+            self.span.record("block_size", block_size);
+        }
+        self.span
+            .record("duration_ms", self.start.elapsed().as_millis() as u64);
+    }
+}
+
+impl Drop for TftpHandler {
+    fn drop(&mut self) {
+        tracing::info!("Served {}", self.server.stats());
+    }
 }
 
 impl From<instant_netboot::Error> for packet::Error {
     fn from(value: instant_netboot::Error) -> Self {
         match value {
-            instant_netboot::Error::InvalidRequestPath => {
-                packet::Error::Msg("Failed to parse request path".to_string())
-            }
+            // TFTP has no code for a malformed path, and an access violation is the closest match
+            instant_netboot::Error::InvalidRequestPath => packet::Error::PermissionDenied,
             instant_netboot::Error::FileNotFound => packet::Error::FileNotFound,
-            instant_netboot::Error::IoError => packet::Error::Msg("I/O error".to_string()),
+            instant_netboot::Error::AccessDenied => packet::Error::PermissionDenied,
+            instant_netboot::Error::IoError(error) => match error.kind() {
+                io::ErrorKind::NotFound => packet::Error::FileNotFound,
+                io::ErrorKind::PermissionDenied => packet::Error::PermissionDenied,
+                io::ErrorKind::StorageFull => packet::Error::DiskFull,
+                _ => packet::Error::Msg(format!("I/O error: {}", error)),
+            },
         }
     }
 }
 
 #[async_trait::async_trait]
 impl async_tftp::server::Handler for TftpHandler {
-    type Reader = Box<dyn AsyncRead + Send + Unpin + 'static>;
+    type Reader = TransferReader;
     type Writer = futures::io::Sink;
 
     async fn read_req_open(
@@ -32,16 +181,384 @@ impl async_tftp::server::Handler for TftpHandler {
         client: &SocketAddr,
         path: &Path,
     ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
-        tracing::debug!("{}: GET {}", client, path.display());
-        Ok((self.server.tftp_get(path).await?, None))
+        let span = tracing::debug_span!(
+            "request",
+            %client,
+            path = %path.display(),
+            action = field::Empty,
+            bytes = field::Empty,
+            block_size = field::Empty,
+            duration_ms = field::Empty,
+        );
+        tracing::debug!(parent: &span, "GET");
+        let start = Instant::now();
+        let admission = match &mut self.rate_limiter {
+            Some(limiter) => limiter.admit(client.ip(), start),
+            None => Admission::Allowed,
+        };
+        // The request is held back by the reader, rather than here, because other requests can't
+        // be handled until this returns. The client's retries are ignored in the meantime, since
+        // they are for a transfer in progress.
+        let delay = match admission {
+            Admission::Allowed => None,
+            Admission::Delayed { wait, first } => {
+                // Only the first delay is logged, so that a flood of requests isn't also a flood
+                // of log messages.
+                if first {
+                    tracing::warn!(parent: &span, "throttling requests from {}", client.ip());
+                }
+                let delay: Pin<Box<dyn Future<Output = ()> + Send>> =
+                    Box::pin(async_std::task::sleep(wait));
+                Some(delay)
+            }
+        };
+        let Some(transfer) = self.transfers.start() else {
+            return Err(packet::Error::Msg("Server is shutting down".to_string()));
+        };
+        match self.server.tftp_open(path).instrument(span.clone()).await {
+            Ok((reader, size)) => Ok((
+                TransferReader {
+                    reader,
+                    delay,
+                    _transfer: transfer,
+                    span,
+                    start,
+                    bytes: 0,
+                    block_size: None,
+                },
+                size,
+            )),
+            Err(error @ instant_netboot::Error::AccessDenied) => {
+                span.record("action", "denied");
+                tracing::warn!(parent: &span, "denied GET");
+                Err(error.into())
+            }
+            Err(error @ instant_netboot::Error::FileNotFound) => {
+                span.record("action", "not-found");
+                Err(error.into())
+            }
+            Err(error) => {
+                span.record("action", "failed");
+                tracing::warn!(parent: &span, "failed GET: {}", error);
+                // Only the undefined error code carries a message, so that is where the path goes
+                Err(match error.into() {
+                    packet::Error::Msg(message) => {
+                        packet::Error::Msg(format!("{}: {}", path.display(), message))
+                    }
+                    error => error,
+                })
+            }
+        }
     }
 
     async fn write_req_open(
         &mut self,
-        _client: &SocketAddr,
-        _path: &Path,
+        client: &SocketAddr,
+        path: &Path,
         _size: Option<u64>,
     ) -> Result<Self::Writer, packet::Error> {
-        Err(packet::Error::IllegalOperation)
+        tracing::debug!(%client, path = %path.display(), "refused PUT");
+        // Boot files are only ever read, and there is nowhere to put a written file. Some PXE
+        // stacks probe the server with a write, and give up quietly on an access violation.
+        if self.allow_write {
+            // TODO: Route writes to a writable backend, once there is one
+            Err(packet::Error::IllegalOperation)
+        } else {
+            Err(packet::Error::PermissionDenied)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::task::block_on;
+    use async_tftp::server::Handler;
+    use boot_loader_entries::syslinux;
+    use std::io;
+
+    /// A server for a single label, booting `kernel`
+    fn server(kernel: &Path) -> instant_netboot::NetbootServer {
+        instant_netboot::NetbootServer::new(
+            syslinux::Label {
+                name: "test".to_string(),
+                kernel: syslinux::Kernel::Kernel(kernel.into()),
+                directives: vec![],
+            }
+            .into(),
+        )
+    }
+
+    #[test]
+    fn access_denied() {
+        let directory = tempfile::tempdir().unwrap();
+        let server = server(Path::new("/Image")).with_root(directory.path().into());
+        let mut handler = TftpHandler {
+            server: server.into(),
+            transfers: shutdown::Transfers::default(),
+            allow_write: false,
+            rate_limiter: None,
+        };
+
+        let client = "192.168.1.100:1024".parse().unwrap();
+        let result = block_on(handler.read_req_open(&client, Path::new("../etc/passwd")));
+        assert!(matches!(result, Err(packet::Error::PermissionDenied)));
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let mut limiter = RateLimiter::new(2, 1.0);
+        let client = "192.168.1.100".parse().unwrap();
+        let start = Instant::now();
+        let after = |millis| start + std::time::Duration::from_millis(millis);
+
+        let delayed = |millis, first| Admission::Delayed {
+            wait: std::time::Duration::from_millis(millis),
+            first,
+        };
+
+        assert_eq!(limiter.admit(client, start), Admission::Allowed);
+        assert_eq!(limiter.admit(client, start), Admission::Allowed);
+        // Requests over the limit wait for the next token, and then the one after that
+        assert_eq!(limiter.admit(client, after(500)), delayed(500, true));
+        assert_eq!(limiter.admit(client, after(500)), delayed(1500, false));
+        // Other clients aren't held back
+        let other = "192.168.1.101".parse().unwrap();
+        assert_eq!(limiter.admit(other, after(500)), Admission::Allowed);
+        // A client retrying after the delays gets a token back
+        assert_eq!(limiter.admit(client, after(3000)), Admission::Allowed);
+        // Clients that have waited long enough are forgotten
+        assert_eq!(limiter.admit(client, after(5000)), Admission::Allowed);
+        assert_eq!(limiter.buckets.len(), 1);
+        assert_eq!(limiter.admit(client, after(10000)), Admission::Allowed);
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn throttle_burst() {
+        use async_std::{future::timeout, net::UdpSocket};
+        use async_tftp::server::TftpServerBuilder;
+
+        let server = server(Path::new("/Image"));
+        block_on(async {
+            let tftpd = TftpServerBuilder::with_handler(TftpHandler {
+                server: server.into(),
+                transfers: shutdown::Transfers::default(),
+                allow_write: false,
+                rate_limiter: Some(RateLimiter::new(1, 2.0)),
+            })
+            .bind("127.0.0.1:0".parse().unwrap())
+            .build()
+            .await
+            .unwrap();
+            let address = tftpd.listen_addr().unwrap();
+
+            // Each request comes from a new port, as a client retrying from the start would
+            let request = || async {
+                let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                socket
+                    .send_to(b"\0\x01pxelinux.cfg/default\0octet\0", address)
+                    .await
+                    .unwrap();
+                socket
+            };
+            let client = async {
+                let mut buffer = [0; 1024];
+                let socket = request().await;
+                socket.recv(&mut buffer).await.unwrap();
+                assert_eq!(buffer[..2], [0, 3]);
+
+                // Nothing is sent back over the limit, not even an error, until the next token
+                let socket = request().await;
+                let wait = Duration::from_millis(200);
+                assert!(timeout(wait, socket.recv(&mut buffer)).await.is_err());
+                socket.recv(&mut buffer).await.unwrap();
+                assert_eq!(buffer[..2], [0, 3]);
+            };
+
+            let serve = tftpd.serve();
+            futures::pin_mut!(serve, client);
+            if let futures::future::Either::Left((result, _)) =
+                futures::future::select(serve, client).await
+            {
+                panic!("server exited: {:?}", result);
+            }
+        });
+    }
+
+    #[test]
+    fn write_refused() {
+        let server: Arc<_> = server(Path::new("/Image")).into();
+        let client = "192.168.1.100:1024".parse().unwrap();
+        let path = Path::new("upload");
+
+        let mut handler = TftpHandler {
+            server: server.clone(),
+            transfers: shutdown::Transfers::default(),
+            allow_write: false,
+            rate_limiter: None,
+        };
+        let result = block_on(handler.write_req_open(&client, path, None));
+        assert!(matches!(result, Err(packet::Error::PermissionDenied)));
+
+        handler.allow_write = true;
+        let result = block_on(handler.write_req_open(&client, path, None));
+        assert!(matches!(result, Err(packet::Error::IllegalOperation)));
+    }
+
+    #[test]
+    fn transfers_tracked() {
+        let server = server(Path::new("/Image"));
+        let transfers = shutdown::Transfers::default();
+        let mut handler = TftpHandler {
+            server: server.into(),
+            transfers: transfers.clone(),
+            allow_write: false,
+            rate_limiter: None,
+        };
+        let client = "192.168.1.100:1024".parse().unwrap();
+        let path = Path::new("pxelinux.cfg/default");
+
+        let reader = block_on(handler.read_req_open(&client, path)).unwrap();
+        assert_eq!(transfers.in_progress(), 1);
+        drop(reader);
+        assert_eq!(transfers.in_progress(), 0);
+
+        assert!(block_on(transfers.drain(std::time::Duration::ZERO)));
+        let result = block_on(handler.read_req_open(&client, path));
+        assert!(matches!(result, Err(packet::Error::Msg(_))));
+    }
+
+    #[test]
+    fn negotiated_block_size() {
+        use async_std::net::UdpSocket;
+        use async_tftp::server::TftpServerBuilder;
+
+        let directory = tempfile::tempdir().unwrap();
+        let kernel = directory.path().join("Image");
+        let contents = (0..3 * 1024 * 1024 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        std::fs::write(&kernel, &contents).unwrap();
+        let server = server(&kernel);
+
+        block_on(async {
+            let tftpd = TftpServerBuilder::with_handler(TftpHandler {
+                server: server.into(),
+                transfers: shutdown::Transfers::default(),
+                allow_write: false,
+                rate_limiter: None,
+            })
+            .bind("127.0.0.1:0".parse().unwrap())
+            .block_size_limit(1024)
+            .build()
+            .await
+            .unwrap();
+            let address = tftpd.listen_addr().unwrap();
+
+            let client = async {
+                let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let mut request = vec![0, 1];
+                request.extend_from_slice(kernel.to_str().unwrap().as_bytes());
+                request.extend_from_slice(b"\0octet\0blksize\x001428\0windowsize\x004\0");
+                socket.send_to(&request, address).await.unwrap();
+
+                // The block size is limited by the server, and windowsize isn't acknowledged
+                let mut buffer = [0; 2048];
+                let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                assert_eq!(&buffer[..length], b"\0\x06blksize\x001024\0");
+                socket.send_to(&[0, 4, 0, 0], peer).await.unwrap();
+
+                let mut received = Vec::new();
+                loop {
+                    let length = socket.recv(&mut buffer).await.unwrap();
+                    assert_eq!(buffer[..2], [0, 3]);
+                    let data = &buffer[4..length];
+                    assert!(data.len() <= 1024);
+                    received.extend_from_slice(data);
+                    socket
+                        .send_to(&[0, 4, buffer[2], buffer[3]], peer)
+                        .await
+                        .unwrap();
+                    if data.len() < 1024 {
+                        break received;
+                    }
+                }
+            };
+
+            let serve = tftpd.serve();
+            futures::pin_mut!(serve, client);
+            match futures::future::select(serve, client).await {
+                futures::future::Either::Right((received, _)) => assert!(received == contents),
+                futures::future::Either::Left((result, _)) => panic!("server exited: {:?}", result),
+            }
+        });
+    }
+
+    #[test]
+    fn io_error_cause() {
+        let error = |kind: io::ErrorKind| match packet::Error::from(
+            instant_netboot::Error::IoError(kind.into()),
+        ) {
+            packet::Error::Msg(message) => message,
+            error => panic!("unexpected error: {:?}", error),
+        };
+        assert_eq!(error(io::ErrorKind::InvalidData), "I/O error: invalid data");
+        assert_eq!(
+            error(io::ErrorKind::UnexpectedEof),
+            "I/O error: unexpected end of file"
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        let error = |error| packet::Error::from(error);
+        assert!(matches!(
+            error(instant_netboot::Error::InvalidRequestPath),
+            packet::Error::PermissionDenied
+        ));
+        assert!(matches!(
+            error(instant_netboot::Error::FileNotFound),
+            packet::Error::FileNotFound
+        ));
+        assert!(matches!(
+            error(instant_netboot::Error::AccessDenied),
+            packet::Error::PermissionDenied
+        ));
+        let io_error = |kind: io::ErrorKind| error(instant_netboot::Error::IoError(kind.into()));
+        assert!(matches!(
+            io_error(io::ErrorKind::NotFound),
+            packet::Error::FileNotFound
+        ));
+        assert!(matches!(
+            io_error(io::ErrorKind::PermissionDenied),
+            packet::Error::PermissionDenied
+        ));
+        assert!(matches!(
+            io_error(io::ErrorKind::StorageFull),
+            packet::Error::DiskFull
+        ));
+    }
+
+    #[test]
+    fn error_message_path() {
+        let directory = tempfile::tempdir().unwrap();
+        // A symlink to itself fails to open, even with permission checks bypassed
+        let kernel = directory.path().join("vmlinuz");
+        std::os::unix::fs::symlink(&kernel, &kernel).unwrap();
+        let mut handler = TftpHandler {
+            server: server(&kernel).into(),
+            transfers: shutdown::Transfers::default(),
+            allow_write: false,
+            rate_limiter: None,
+        };
+        let client = "127.0.0.1:1234".parse().unwrap();
+        match block_on(handler.read_req_open(&client, &kernel)) {
+            Err(packet::Error::Msg(message)) => {
+                assert!(message.starts_with(&format!("{}: I/O error", kernel.display())))
+            }
+            Ok(_) => panic!("opened a symlink loop"),
+            Err(error) => panic!("unexpected error: {:?}", error),
+        }
     }
 }